@@ -8,6 +8,7 @@ fn main() {
         Config {
             stylesheets: Vec::new(),
             base_url: Some(String::from("https://www.google.com/")),
+            ..Default::default()
         },
     );
 }
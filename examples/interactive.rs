@@ -11,8 +11,15 @@ fn main() {
 }
 
 fn app() -> Element {
+    let mut count = use_signal(|| 0);
+
     rsx! {
         style { {CSS} }
+        div {
+            class: "counter",
+            onclick: move |_| count += 1,
+            "Clicked {count} times"
+        }
         h1 { "h1" }
         h2 { "h2" }
         h3 { "h3" }
@@ -45,4 +52,9 @@ h4 {
 .header {
     background-color: pink;
 }
+
+.counter {
+    background-color: lightblue;
+    padding: 10px;
+}
 "#;
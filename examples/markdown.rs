@@ -1,7 +1,5 @@
 //! Render the readme.md using the gpu renderer
 
-use std::{ffi::OsStr, path::Path};
-
 use comrak::{markdown_to_html, Options};
 use dioxus_blitz::Config;
 
@@ -22,19 +20,15 @@ fn main() {
         body_html
     );
 
-    let f = file!();
-
-    let mut base_path = Path::new(OsStr::new(&f)).to_owned();
-    base_path.pop();
+    // Images in the readme are relative to the root of the repo
+    let base_url = format!("file://{}/", env!("CARGO_MANIFEST_DIR"));
 
     dioxus_blitz::launch_static_html_cfg(
         &html,
         Config {
             stylesheets: vec![String::from(stylesheet)],
-            base_url: Some(format!(
-                "file://{}",
-                base_path.as_os_str().to_string_lossy()
-            )),
+            base_url: Some(base_url),
+            ..Default::default()
         },
     );
 }
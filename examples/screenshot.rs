@@ -0,0 +1,65 @@
+//! Render a HTML document to a PNG without opening a window
+//!
+//! Usage: cargo run --example screenshot [output.png]
+
+use blitz::Viewport;
+use blitz_dom::{Document, DocumentHtmlParser};
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 600;
+
+fn main() {
+    let out_path = std::env::args()
+        .skip(1)
+        .next()
+        .unwrap_or_else(|| "screenshot.png".into());
+
+    let mut doc = Document::new(Viewport::new((WIDTH, HEIGHT)).make_device());
+    doc.add_stylesheet(CSS);
+    DocumentHtmlParser::parse_into_doc(&mut doc, HTML);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let image = match rt.block_on(blitz::render_to_image(&mut doc, WIDTH, HEIGHT, 1.0)) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("Failed to render: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    image.save(&out_path).expect("Failed to write image");
+    println!("Wrote {}", out_path);
+}
+
+const HTML: &str = r#"
+<!DOCTYPE html>
+<html>
+<body>
+    <div class="card">
+        <h1>Hello from Blitz</h1>
+        <div class="swatch red"></div>
+        <div class="swatch green"></div>
+        <div class="swatch blue"></div>
+    </div>
+</body>
+</html>
+"#;
+
+const CSS: &str = r#"
+.card {
+    margin: 20px;
+    padding: 20px;
+    border: 2px solid #333;
+    border-radius: 8px;
+}
+
+.swatch {
+    width: 100px;
+    height: 40px;
+    margin-top: 10px;
+}
+
+.red { background-color: red; }
+.green { background-color: green; }
+.blue { background-color: blue; }
+"#;
@@ -5,7 +5,6 @@ lib.rs -> DioxusNative -> Window -> Document
 */
 
 mod devtools;
-mod fontcache;
 mod imagecache;
 pub mod render;
 mod text;
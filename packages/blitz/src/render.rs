@@ -1,20 +1,24 @@
+mod headless;
 mod multicolor_rounded_rect;
 
+pub use headless::{render_to_image, HeadlessError};
+
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 // So many imports
 use self::multicolor_rounded_rect::{Edge, ElementFrame};
 use crate::{
     devtools::Devtools,
-    // fontcache::FontCache,
     // imagecache::ImageCache,
     text::TextContext,
     util::{GradientSlice, StyloGradient, ToVelloColor},
     viewport::Viewport,
 };
 use blitz_dom::{
-    node::{NodeData, TextNodeData},
-    DocumentLike, Node,
+    events::{EventData, RendererEvent},
+    node::NodeData,
+    text::{FontContext, TextLayout, TextStyle},
+    Document, DocumentLike, Node,
 };
 use html5ever::local_name;
 use image::{imageops::FilterType, DynamicImage};
@@ -38,7 +42,7 @@ use style::{
 use taffy::prelude::Layout;
 use vello::{
     kurbo::{Affine, Point, Rect, Shape, Stroke, Vec2},
-    peniko::{self, Color, Fill},
+    peniko::{self, Color, Fill, Mix},
     util::RenderContext,
     util::RenderSurface,
     AaSupport, RenderParams, Renderer as VelloRenderer, RendererOptions, Scene,
@@ -83,7 +87,8 @@ pub struct Renderer<'s, W, Doc: DocumentLike> {
     pub devtools: Devtools,
 
     hover_node_id: Option<usize>,
-    scroll_offset: f64,
+    /// The last known position of the mouse in CSS pixels
+    mouse_pos: (f32, f32),
 }
 
 impl<'a, W, Doc: DocumentLike> Renderer<'a, W, Doc>
@@ -111,12 +116,13 @@ where
             // fonts: Default::default(),
             devtools: Default::default(),
             hover_node_id: Default::default(),
-            scroll_offset: 0.0,
+            mouse_pos: (0.0, 0.0),
         }
     }
 
-    pub fn poll(&mut self, cx: std::task::Context) {
-        self.dom.poll(cx);
+    /// Poll the document for pending work. Returns true if the document changed and needs to be redrawn
+    pub fn poll(&mut self, cx: std::task::Context) -> bool {
+        self.dom.poll(cx)
     }
 
     pub async fn resume(&mut self, window_builder: impl FnOnce() -> (Arc<W>, Viewport)) {
@@ -151,7 +157,7 @@ where
         let options = RendererOptions {
             surface_format: Some(surface.config.format),
             antialiasing_support: AaSupport::all(),
-            use_cpu: false,
+            use_cpu: cfg!(feature = "cpu"),
             num_init_threads: default_threads(),
         };
 
@@ -197,46 +203,65 @@ where
 
     pub fn mouse_move(&mut self, x: f32, y: f32) -> bool {
         let old_id = self.hover_node_id;
+        self.mouse_pos = (x, y);
         self.hover_node_id = self.dom.as_ref().hit(x, y);
+
+        if let Some(target) = self.hover_node_id {
+            self.dom.handle_event(RendererEvent {
+                target,
+                data: EventData::MouseMove { x, y },
+            });
+        }
+
+        let restyle = self.dom.as_mut().set_hover_node(self.hover_node_id);
         if old_id != self.hover_node_id {
             // println!("Hovered node: {:?}", self.hover_node_id);
-            self.devtools.highlight_hover
+            restyle || self.devtools.highlight_hover
         } else {
             false
         }
     }
 
-    pub fn scroll_by(&mut self, px: f64) {
-        // Invert scrolling on macos
-        #[cfg(target_os = "macos")]
-        {
-            self.scroll_offset += px;
-        }
-        #[cfg(not(target_os = "macos"))]
-        {
-            self.scroll_offset -= px;
-        }
+    /// The pointer left the window, so nothing is hovered any more
+    pub fn mouse_leave(&mut self) -> bool {
+        self.hover_node_id = None;
+        self.dom.as_mut().set_hover_node(None)
+    }
 
-        self.clamp_scroll();
+    /// Press the pointer on whatever it's over, making it `:active` and focusing it. Returns true if the document
+    /// needs to be redrawn.
+    pub fn mouse_down(&mut self) -> bool {
+        let doc = self.dom.as_mut();
+        let active_changed = doc.set_active_node(self.hover_node_id);
+        let focus_changed = doc.set_focus_node(self.hover_node_id);
+        active_changed || focus_changed
     }
 
-    /// Clamp scroll offset
-    fn clamp_scroll(&mut self) {
-        let content_height = self.dom.as_ref().root_element().final_layout.size.height as f64;
-        let viewport_height = self
-            .dom
-            .as_mut()
-            .stylist_device()
-            .au_viewport_size()
-            .height
-            .to_f64_px();
-        self.scroll_offset = self
-            .scroll_offset
-            .min(0.0)
-            .max(-(content_height - viewport_height));
+    /// Release the pointer. Returns true if the document needs to be redrawn.
+    pub fn mouse_up(&mut self) -> bool {
+        self.dom.as_mut().set_active_node(None)
+    }
+
+    /// Scroll whatever is under the mouse, starting with the innermost scroll container
+    pub fn scroll_by(&mut self, dx: f64, dy: f64) {
+        // Invert scrolling on macos
+        #[cfg(target_os = "macos")]
+        let (dx, dy) = (-dx, -dy);
+
+        let (x, y) = self.mouse_pos;
+        let target = self.dom.as_ref().hit(x, y);
+        self.dom.as_mut().scroll_node_by(target, dx, dy);
     }
 
     pub fn click(&mut self) {
+        if let Some(target) = self.hover_node_id {
+            let (x, y) = self.mouse_pos;
+            self.dom.handle_event(RendererEvent {
+                target,
+                data: EventData::Click { x, y },
+            });
+        }
+
         if self.devtools.highlight_hover {
             if let Some(node_id) = self.hover_node_id {
                 let node = &self.dom.as_ref().tree()[node_id];
@@ -284,7 +309,8 @@ where
             dbg!(&state.viewport);
             self.render_context
                 .resize_surface(&mut state.surface, width, height);
-            self.clamp_scroll();
+            // Keep the scroll position within the resized page
+            self.dom.as_mut().scroll_viewport_by(0.0);
         }
     }
 
@@ -294,28 +320,19 @@ where
     /// This assumes styles are resolved and layout is complete.
     /// Make sure you do those before trying to render
     pub fn render(&mut self, scene: &mut Scene) {
-        // Simply render the document (the root element (note that this is not the same as the root node)))
-        scene.reset();
-        self.render_element(
-            scene,
-            self.dom.as_ref().root_element().id,
-            Point {
-                x: 0.0,
-                y: self.scroll_offset,
-            },
-        );
-
-        // Render debug overlay
-        if self.devtools.highlight_hover {
-            if let Some(node_id) = self.hover_node_id {
-                self.render_debug_overlay(scene, node_id);
-            }
-        }
-
         let RenderState::Active(state) = &mut self.render_state else {
             return;
         };
 
+        let generator = SceneGenerator {
+            dom: self.dom.as_ref(),
+            scale: state.viewport.scale_f64(),
+            text_context: &self.text_context,
+            devtools: self.devtools,
+            hover_node_id: self.hover_node_id,
+        };
+        generator.generate_vello_scene(scene);
+
         let surface_texture = state
             .surface
             .surface
@@ -345,16 +362,53 @@ where
         surface_texture.present();
         device.device.poll(wgpu::Maintain::Wait);
     }
+}
+
+/// Builds a Vello [`Scene`] from a document whose styles and layout have already been resolved.
+///
+/// This is independent of any window or render surface, so the same scene can be presented to a window
+/// or rasterized offscreen (see [`render_to_image`]).
+pub(crate) struct SceneGenerator<'dom> {
+    pub(crate) dom: &'dom Document,
+    pub(crate) scale: f64,
+    pub(crate) text_context: &'dom TextContext,
+    pub(crate) devtools: Devtools,
+    pub(crate) hover_node_id: Option<usize>,
+}
+
+impl<'dom> SceneGenerator<'dom> {
+    /// Reset the scene and draw the document into it
+    pub(crate) fn generate_vello_scene(&self, scene: &mut Scene) {
+        // Simply render the document (the root element (note that this is not the same as the root node)))
+        scene.reset();
+        self.render_element(
+            scene,
+            self.dom.root_element().id,
+            Point {
+                x: 0.0,
+                y: -self.dom.viewport_scroll(),
+            },
+        );
+
+        // Fixed elements are positioned against the viewport, on top of the page
+        for node_id in self.dom.fixed_nodes() {
+            self.render_element(scene, *node_id, Point::ZERO);
+        }
+
+        // Render debug overlay
+        if self.devtools.highlight_hover {
+            if let Some(node_id) = self.hover_node_id {
+                self.render_debug_overlay(scene, node_id);
+            }
+        }
+    }
 
     /// Renders a layout debugging overlay which visualises the content size, padding and border
     /// of the node with a transparent overlay.
     fn render_debug_overlay(&self, scene: &mut Scene, node_id: usize) {
-        let RenderState::Active(state) = &self.render_state else {
-            return;
-        };
-        let scale = state.viewport.scale_f64();
+        let scale = self.scale;
 
-        let mut node = &self.dom.as_ref().tree()[node_id];
+        let mut node = &self.dom.tree()[node_id];
 
         let taffy::Layout {
             size,
@@ -376,8 +430,8 @@ where
 
         let mut abs_x = x;
         let mut abs_y = y;
-        while let Some(parent_id) = node.parent {
-            node = &self.dom.as_ref().tree()[parent_id];
+        while let Some(parent_id) = node.layout_parent.or(node.parent) {
+            node = &self.dom.tree()[parent_id];
             let taffy::Point { x, y } = node.final_layout.location;
             abs_x += x;
             abs_y += y;
@@ -491,7 +545,7 @@ where
         //  - custom_properties, writing_mode, rules, visited_style, flags,  box_, column, counters, effects,
         //  - inherited_box, inherited_table, inherited_text, inherited_ui,
 
-        let element = &self.dom.as_ref().tree()[node];
+        let element = &self.dom.tree()[node];
 
         // Early return if the element is hidden
         if matches!(element.style.display, taffy::prelude::Display::None) {
@@ -522,25 +576,43 @@ where
         cx.stroke_devtools(scene);
         cx.draw_image(scene);
 
-        for child in &cx.element.children {
-            match &self.dom.as_ref().tree()[*child].raw_dom_data {
-                NodeData::Element(_) => self.render_element(scene, *child, cx.pos),
-                NodeData::Text(TextNodeData { content }) => {
-                    let (_layout, pos) = self.node_position(*child, cx.pos);
-                    cx.stroke_text(scene, &self.text_context, &content, pos)
+        // Unless overflow is visible, children are clipped to the (rounded) padding box
+        let clips_overflow = cx.element.clips_overflow();
+        if clips_overflow {
+            scene.push_layer(Mix::Clip, 1.0, cx.transform, &cx.frame.frame());
+        }
+
+        // The content of scroll containers is moved by their scroll offset
+        let scroll = cx.element.scroll_offset;
+        let content_pos = cx.pos - Vec2::new(scroll.x as f64, scroll.y as f64);
+
+        for child in &cx.element.layout_children {
+            match &self.dom.tree()[*child].raw_dom_data {
+                // Fixed elements are drawn after the rest of the page
+                NodeData::Element(_) if self.dom.tree()[*child].is_fixed() => {}
+                NodeData::Element(_) => self.render_element(scene, *child, content_pos),
+                NodeData::Text(_) => {
+                    let (_layout, pos) = self.node_position(*child, content_pos);
+                    if let Some(text_layout) = &self.dom.tree()[*child].text_layout {
+                        let fonts = self.dom.fonts();
+                        cx.stroke_text(scene, self.text_context, fonts, text_layout, pos)
+                    }
                 }
                 NodeData::Document => {}
                 // NodeData::Doctype => {}
                 NodeData::Comment => {} // NodeData::ProcessingInstruction { .. } => {}
             }
         }
+
+        if clips_overflow {
+            scene.pop_layer();
+        }
+        if cx.element.is_scroll_container() {
+            cx.draw_scrollbars(scene);
+        }
     }
 
     fn element_cx<'w>(&'w self, element: &'w Node, location: Point) -> ElementCx {
-        let RenderState::Active(state) = &self.render_state else {
-            panic!("Renderer is not active");
-        };
-
         let style = element
             .stylo_element_data
             .borrow()
@@ -551,11 +623,10 @@ where
             .clone();
 
         let (layout, pos) = self.node_position(element.id, location);
-        let scale = state.viewport.scale_f64();
+        let scale = self.scale;
 
         let inherited_text = style.get_inherited_text();
-        let font = style.get_font();
-        let font_size = font.font_size.computed_size().px() as f32;
+        let text_style = TextStyle::from_computed(&style, self.dom.fonts());
         let text_color = inherited_text.clone_color().as_vello();
 
         // the bezpaths for every element are (potentially) cached (not yet, tbd)
@@ -574,7 +645,7 @@ where
             // layout,
             pos,
             element,
-            font_size,
+            text_style,
             text_color,
             transform,
             image: element.element_data().unwrap().image.clone(),
@@ -584,12 +655,13 @@ where
 
     fn node_position(&self, node: usize, location: Point) -> (Layout, Point) {
         let layout = self.layout(node);
-        let pos = location + Vec2::new(layout.location.x as f64, layout.location.y as f64);
+        let offset = layout.location + self.dom.tree()[node].sticky_offset;
+        let pos = location + Vec2::new(offset.x as f64, offset.y as f64);
         (layout, pos)
     }
 
     fn layout(&self, child: usize) -> Layout {
-        self.dom.as_ref().tree()[child].unrounded_layout
+        self.dom.tree()[child].unrounded_layout
         // self.dom.tree()[child].final_layout
     }
 }
@@ -602,7 +674,7 @@ struct ElementCx<'a> {
     pos: Point,
     scale: f64,
     element: &'a Node,
-    font_size: f32,
+    text_style: TextStyle,
     text_color: Color,
     transform: Affine,
     image: Option<Arc<DynamicImage>>,
@@ -614,19 +686,20 @@ impl ElementCx<'_> {
         &self,
         scene: &mut Scene,
         text_context: &TextContext,
-        contents: &str,
+        fonts: &FontContext,
+        text_layout: &TextLayout,
         pos: Point,
     ) {
-        let transform = Affine::translate((pos.x * self.scale, pos.y * self.scale))
-            .then_translate((0.0, self.font_size as f64 * self.scale as f64).into());
+        let transform =
+            Affine::translate((pos.x * self.scale, pos.y * self.scale)) * Affine::scale(self.scale);
 
         text_context.add(
             scene,
-            None,
-            self.font_size * self.scale as f32,
+            fonts,
+            self.text_style.font_size,
             Some(self.text_color),
             transform,
-            contents,
+            text_layout,
         )
     }
 
@@ -656,6 +729,46 @@ impl ElementCx<'_> {
         }
     }
 
+    /// Draw overlay scrollbars along the right and bottom of a scroll container's padding box, sized and placed to
+    /// show which part of its content is in view
+    fn draw_scrollbars(&self, scene: &mut Scene) {
+        const THICKNESS: f64 = 6.0;
+        const INSET: f64 = 2.0;
+        const MIN_LENGTH: f64 = 20.0;
+
+        let range = self.element.scroll_range();
+        let offset = self.element.scroll_offset;
+        let padding_box = self.frame.inner_rect;
+        let (thickness, inset) = (THICKNESS * self.scale, INSET * self.scale);
+        let color = Color::rgba(0.0, 0.0, 0.0, 0.4);
+
+        // The thumb is to the track what the visible part of the content is to all of it
+        let thumb = |track: f64, range: f32, offset: f32| {
+            let range = range as f64 * self.scale;
+            let length = (track * track / (track + range)).max(MIN_LENGTH * self.scale);
+            let start = (track - length) * offset as f64 * self.scale / range;
+            (start, length)
+        };
+
+        if range.y > 0.0 {
+            let (start, length) = thumb(padding_box.height(), range.y, offset.y);
+            let x1 = padding_box.x1 - inset;
+            let y0 = padding_box.y0 + start;
+            let rect = Rect::new(x1 - thickness, y0, x1, y0 + length);
+            let shape = rect.to_rounded_rect(thickness / 2.0);
+            scene.fill(Fill::NonZero, self.transform, color, None, &shape);
+        }
+
+        if range.x > 0.0 {
+            let (start, length) = thumb(padding_box.width(), range.x, offset.x);
+            let x0 = padding_box.x0 + start;
+            let y1 = padding_box.y1 - inset;
+            let rect = Rect::new(x0, y1 - thickness, x0 + length, y1);
+            let shape = rect.to_rounded_rect(thickness / 2.0);
+            scene.fill(Fill::NonZero, self.transform, color, None, &shape);
+        }
+    }
+
     fn stroke_devtools(&self, scene: &mut Scene) {
         if self.devtools.show_layout {
             let shape = &self.frame.outer_rect;
@@ -1011,7 +1124,8 @@ impl ElementCx<'_> {
             .unwrap_or_default();
 
         let style = match outline_style {
-            OutlineStyle::Auto => return,
+            // Focus rings are drawn as plain solid outlines
+            OutlineStyle::Auto => BorderStyle::Solid,
             OutlineStyle::BorderStyle(BorderStyle::Hidden) => return,
             OutlineStyle::BorderStyle(BorderStyle::None) => return,
             OutlineStyle::BorderStyle(style) => style,
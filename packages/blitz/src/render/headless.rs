@@ -0,0 +1,219 @@
+//! Render a document to an image without a window
+//!
+//! This builds the scene with the same pipeline as the windowed renderer, but rasterizes it into an offscreen
+//! texture which is then read back into CPU memory.
+//!
+//! Rendering always goes through a wgpu adapter. With the `cpu` feature that is wgpu's software fallback adapter
+//! (Mesa's llvmpipe/lavapipe on Linux, WARP on Windows), which has to be installed on machines without a GPU.
+
+use super::SceneGenerator;
+use crate::{devtools::Devtools, text::TextContext, viewport::Viewport};
+use blitz_dom::Document;
+use image::RgbaImage;
+use std::fmt;
+#[cfg(not(feature = "cpu"))]
+use vello::util::RenderContext;
+use vello::{
+    peniko::Color, AaConfig, AaSupport, RenderParams, Renderer as VelloRenderer, RendererOptions,
+    Scene,
+};
+use wgpu::{
+    BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, Extent3d, ImageCopyBuffer,
+    ImageDataLayout, MapMode, Queue, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureViewDescriptor,
+};
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    /// wgpu couldn't find an adapter to render with. With the `cpu` feature this means no software rasterizer is
+    /// installed.
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+    Render(vello::Error),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(feature = "cpu"))]
+            HeadlessError::NoAdapter => write!(f, "No compatible graphics adapter found"),
+            #[cfg(feature = "cpu")]
+            HeadlessError::NoAdapter => write!(
+                f,
+                "No software adapter found. Is a software rasterizer (e.g. Mesa llvmpipe) installed?"
+            ),
+            HeadlessError::RequestDevice(err) => write!(f, "Failed to create device: {}", err),
+            HeadlessError::Render(err) => write!(f, "Failed to render: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {}
+
+/// Wait for the document's images and stylesheets to load, resolve styles and layout at the given size, then draw it
+/// into an RGBA image.
+///
+/// `width` and `height` are the size of the output image in physical pixels and `scale` is the device pixel ratio,
+/// so the document is laid out against a viewport of `width / scale` by `height / scale` CSS pixels.
+pub async fn render_to_image(
+    doc: &mut Document,
+    width: u32,
+    height: u32,
+    scale: f32,
+) -> Result<RgbaImage, HeadlessError> {
+    let mut viewport = Viewport::new((width, height));
+    viewport.set_hidpi_scale(scale);
+
+    doc.load_resources().await;
+    doc.set_stylist_device(viewport.make_device());
+    doc.resolve();
+
+    let text_context = TextContext::default();
+    let mut scene = Scene::new();
+    SceneGenerator {
+        dom: doc,
+        scale: viewport.scale_f64(),
+        text_context: &text_context,
+        devtools: Devtools::default(),
+        hover_node_id: None,
+    }
+    .generate_vello_scene(&mut scene);
+
+    rasterize(&scene, width, height).await
+}
+
+/// Rasterize a scene into an offscreen texture and copy the result back into an image
+async fn rasterize(scene: &Scene, width: u32, height: u32) -> Result<RgbaImage, HeadlessError> {
+    let (device, queue) = create_device().await?;
+    let (device, queue) = (&device, &queue);
+
+    let mut renderer = VelloRenderer::new(
+        device,
+        RendererOptions {
+            surface_format: None,
+            antialiasing_support: AaSupport::all(),
+            use_cpu: cfg!(feature = "cpu"),
+            num_init_threads: None,
+        },
+    )
+    .map_err(HeadlessError::Render)?;
+
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let target = device.create_texture(&TextureDescriptor {
+        label: Some("Target texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = target.create_view(&TextureViewDescriptor::default());
+
+    let render_params = RenderParams {
+        base_color: Color::WHITE,
+        width,
+        height,
+        antialiasing_method: AaConfig::Msaa16,
+    };
+    renderer
+        .render_to_texture(device, queue, scene, &view, &render_params)
+        .map_err(HeadlessError::Render)?;
+
+    // Rows copied out of a texture must be padded to wgpu's row alignment
+    let unpadded_row_bytes = width * 4;
+    let padded_row_bytes =
+        wgpu::util::align_to(unpadded_row_bytes, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback buffer"),
+        size: padded_row_bytes as u64 * height as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Copy out buffer"),
+    });
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit([encoder.finish()]);
+
+    let buffer_slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer_slice.map_async(MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .expect("Readback buffer was dropped before being mapped")
+        .expect("failed to map readback buffer");
+
+    // Strip the row padding
+    let data = buffer_slice.get_mapped_range();
+    let mut pixels = Vec::with_capacity((unpadded_row_bytes * height) as usize);
+    for row in data.chunks(padded_row_bytes as usize) {
+        pixels.extend_from_slice(&row[..unpadded_row_bytes as usize]);
+    }
+    drop(data);
+    buffer.unmap();
+
+    Ok(RgbaImage::from_raw(width, height, pixels).expect("pixel buffer matches image size"))
+}
+
+/// Pick whichever adapter wgpu would normally choose (this can be overridden with the `WGPU_ADAPTER_NAME` and
+/// `WGPU_BACKEND` environment variables)
+#[cfg(not(feature = "cpu"))]
+async fn create_device() -> Result<(Device, Queue), HeadlessError> {
+    let mut render_context = RenderContext::new().map_err(HeadlessError::Render)?;
+    let dev_id = render_context
+        .device(None)
+        .await
+        .ok_or(HeadlessError::NoAdapter)?;
+    let device_handle = render_context.devices.swap_remove(dev_id);
+    Ok((device_handle.device, device_handle.queue))
+}
+
+/// Software rendering: Vello runs its pipeline stages on the CPU and the final rasterization pass runs on wgpu's
+/// fallback adapter (e.g. Mesa's llvmpipe/lavapipe or WARP), so no GPU is required but the fallback adapter is.
+#[cfg(feature = "cpu")]
+async fn create_device() -> Result<(Device, Queue), HeadlessError> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+        ..Default::default()
+    });
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::None,
+            force_fallback_adapter: true,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or(HeadlessError::NoAdapter)?;
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::default(),
+            },
+            None,
+        )
+        .await
+        .map_err(HeadlessError::RequestDevice)
+}
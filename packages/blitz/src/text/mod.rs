@@ -1,13 +1,8 @@
 use std::cell::RefCell;
 
+use blitz_dom::text::{FontContext, FontId, TextLayout};
 use vello::{glyph::skrifa::raw::FileRef, skrifa::prelude::*, Scene};
-use vello::{
-    glyph::GlyphContext,
-    kurbo::Affine,
-    peniko::{Brush, Font},
-};
-
-const FONT_DATA: &[u8] = include_bytes!("Roboto-Regular.ttf");
+use vello::{glyph::GlyphContext, kurbo::Affine, peniko::Brush};
 
 #[derive(Default)]
 pub struct TextContext {
@@ -15,93 +10,61 @@ pub struct TextContext {
 }
 
 impl TextContext {
-    /// way more to this than meets the eye
+    /// Draw text that has already been shaped, broken into lines and positioned by blitz-dom.
+    ///
+    /// `size` and the layout are in the same units, which `transform` maps to device pixels.
     /// We'll want to add a parameter for style stacks (underline, fontweight, strike-thru, etc)
     /// https://github.com/dfrg/parley/blob/master/src/resolve/mod.rs
     pub fn add(
         &self,
         builder: &mut Scene,
-        font: Option<&Font>,
+        fonts: &FontContext,
         size: f32,
         brush: Option<impl Into<Brush>>,
         transform: Affine,
-        text: &str,
+        layout: &TextLayout,
     ) {
-        let font = font.and_then(to_font_ref).unwrap_or_else(default_font);
-        let fello_size = Size::new(size);
-        let charmap = font.charmap();
-        let metrics = font.metrics(fello_size, LocationRef::default());
-        let line_height = metrics.ascent - metrics.descent + metrics.leading;
-        let glyph_metrics = font.glyph_metrics(fello_size, LocationRef::default());
-        let mut pen_x = 0f64;
-        let mut pen_y = 0f64;
         let vars: [(&str, f32); 0] = [];
+        let brush = brush.map(Into::into);
 
-        let mut gcx = self.gcx.borrow_mut();
+        // Runs that needed a fallback font are drawn with that font, one font at a time
+        let mut font_ids: Vec<FontId> = layout
+            .lines
+            .iter()
+            .flat_map(|line| line.glyphs.iter().map(|glyph| glyph.font))
+            .collect();
+        font_ids.sort_unstable();
+        font_ids.dedup();
 
-        let mut provider = gcx.new_provider(&font, size, false, vars);
-        // let mut provider = gcx.new_provider(&font, None, size, false, vars);
-        let brush = brush.map(Into::into);
-        for ch in text.chars() {
-            if ch == '\n' {
-                pen_y += line_height as f64;
-                pen_x = 0.0;
+        let mut gcx = self.gcx.borrow_mut();
+        for font_id in font_ids {
+            let font_data = fonts.font_data(font_id);
+            let Some(font) = to_font_ref(&font_data.data, font_data.index) else {
                 continue;
+            };
+
+            let mut provider = gcx.new_provider(&font, size, false, vars);
+            for line in &layout.lines {
+                for glyph in line.glyphs.iter().filter(|glyph| glyph.font == font_id) {
+                    if let Some(outline) = provider.get(glyph.id, brush.as_ref()) {
+                        let xform = transform
+                            * Affine::translate((
+                                (line.x + glyph.x) as f64,
+                                (line.baseline + glyph.y) as f64,
+                            ))
+                            * Affine::scale_non_uniform(1.0, -1.0);
+                        builder.append(&outline, Some(xform));
+                    }
+                }
             }
-            let gid = charmap.map(ch).unwrap_or_default();
-            let advance = glyph_metrics.advance_width(gid).unwrap_or_default() as f64;
-            if let Some(glyph) = provider.get(gid.to_u16(), brush.as_ref()) {
-                let xform = transform
-                    * Affine::translate((pen_x, pen_y))
-                    * Affine::scale_non_uniform(1.0, -1.0);
-                builder.append(&glyph, Some(xform));
-            }
-            pen_x += advance;
         }
     }
-
-    // pub fn get_text_size<'a>(
-    //     &'a self,
-    //     font: Option<&'a Font>,
-    //     size: f32,
-    //     text: &str,
-    // ) -> (f64, f64) {
-    //     let font = font.and_then(to_font_ref).unwrap_or_else(default_font);
-    //     let fello_size = Size::new(size);
-    //     let charmap = font.charmap();
-    //     let metrics = font.metrics(fello_size, LocationRef::default());
-    //     let line_height = metrics.ascent - metrics.descent + metrics.leading;
-    //     let glyph_metrics = font.glyph_metrics(fello_size, LocationRef::default());
-    //     let mut max_width = 0;
-    //     let mut cur_width = 0f64;
-    //     let mut height = line_height as f64;
-
-    //     for ch in text.chars() {
-    //         if ch == '\n' {
-    //             height += line_height as f64;
-    //             max_width = max_width.max(cur_width as i32);
-    //             cur_width = 0.0;
-    //             continue;
-    //         }
-    //         let gid = charmap.map(ch).unwrap_or_default();
-    //         let advance = glyph_metrics.advance_width(gid).unwrap_or_default() as f64;
-    //         cur_width += advance;
-    //     }
-
-    //     max_width = max_width.max(cur_width as i32);
-
-    //     (max_width as _, height)
-    // }
 }
 
-fn to_font_ref(font: &Font) -> Option<FontRef> {
-    let file_ref = FileRef::new(font.data.as_ref()).ok()?;
+fn to_font_ref(data: &[u8], index: u32) -> Option<FontRef> {
+    let file_ref = FileRef::new(data).ok()?;
     match file_ref {
         FileRef::Font(font) => Some(font),
-        FileRef::Collection(collection) => collection.get(font.index).ok(),
+        FileRef::Collection(collection) => collection.get(index).ok(),
     }
 }
-
-fn default_font<'a>() -> FontRef<'a> {
-    FontRef::new(FONT_DATA).unwrap()
-}
@@ -1,20 +1,33 @@
 //! Integration between Dioxus and Blitz
 
+use std::rc::Rc;
+
 use blitz::Viewport;
 use blitz_dom::{
-    namespace_url, node::Attribute, ns, Atom, Document, DocumentLike, ElementNodeData, NodeData,
-    QualName, TextNodeData,
+    events::{EventData, RendererEvent},
+    namespace_url,
+    node::Attribute,
+    ns, Atom, Document, DocumentLike, ElementNodeData, NodeData, QualName, TextNodeData,
 };
 
-use dioxus::dioxus_core::{
-    AttributeValue, ElementId, Template, TemplateAttribute, TemplateNode, VirtualDom,
-    WriteMutations,
+use dioxus::{
+    dioxus_core::{
+        AttributeValue, ElementId, Template, TemplateAttribute, TemplateNode, VirtualDom,
+        WriteMutations,
+    },
+    html::{input_data::MouseButton, set_event_converter, PlatformEventData},
 };
 use futures_util::{pin_mut, FutureExt};
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::events::{BlitzEventConverter, BlitzMouseData};
 
 type NodeId = usize;
 
+/// The attribute used to tag elements in the Blitz document with the Dioxus ElementId they are mounted as
+const DIOXUS_ID_ATTR: &str = "data-dioxus-id";
+
+/// Un-namespaced elements are HTML elements
 fn qual_name(local_name: &str, namespace: Option<&str>) -> QualName {
     QualName {
         prefix: None,
@@ -23,6 +36,16 @@ fn qual_name(local_name: &str, namespace: Option<&str>) -> QualName {
     }
 }
 
+/// Un-namespaced attributes are in the null namespace (as html5ever parses them), not the HTML namespace, so that
+/// attribute selectors without a namespace prefix match them
+fn attr_qual_name(local_name: &str, namespace: Option<&str>) -> QualName {
+    QualName {
+        prefix: None,
+        ns: namespace.map(Atom::from).unwrap_or(ns!()),
+        local: Atom::from(local_name),
+    }
+}
+
 pub struct DioxusDocument {
     vdom: VirtualDom,
     vdom_state: DioxusState,
@@ -47,7 +70,8 @@ impl Into<Document> for DioxusDocument {
     }
 }
 impl DocumentLike for DioxusDocument {
-    fn poll(&mut self, mut cx: std::task::Context) {
+    fn poll(&mut self, mut cx: std::task::Context) -> bool {
+        let mut changed = self.inner.poll_resources(&mut cx);
         loop {
             {
                 let fut = self.vdom.wait_for_work();
@@ -58,6 +82,31 @@ impl DocumentLike for DioxusDocument {
                     std::task::Poll::Pending => break,
                 }
             }
+
+            self.apply_mutations();
+            changed = true;
+        }
+
+        changed
+    }
+
+    fn handle_event(&mut self, event: RendererEvent) {
+        let name = event.name();
+
+        // Walk up the parent chain from the target until we find an element listening for this event.
+        // Dioxus takes care of bubbling the event further up the tree from there.
+        let mut next = Some(event.target);
+        while let Some(node_id) = next {
+            let node = &self.inner.tree()[node_id];
+            let element_id = node.element_data().and_then(dioxus_id);
+            if let Some(element_id) = element_id {
+                if self.vdom_state.has_listener(element_id, name) {
+                    let data = self.event_data(&event);
+                    self.vdom.handle_event(name, data, element_id, true);
+                    return;
+                }
+            }
+            next = node.parent;
         }
     }
 }
@@ -70,6 +119,9 @@ impl DioxusDocument {
         // Include default and user-specified stylesheets
         doc.add_stylesheet(include_str!("./default.css"));
 
+        // Teach dioxus-html how to read the event data we dispatch
+        set_event_converter(Box::new(BlitzEventConverter));
+
         let state = DioxusState::create(&mut doc);
         let mut doc = Self {
             vdom,
@@ -95,17 +147,34 @@ impl DioxusDocument {
         // dbg!(writer.state);
     }
 
-    // pub fn apply_mutations(&mut self) {
-    //     // Apply the mutations to the actual dom
-    //     let mut writer = MutationWriter {
-    //         doc: &mut self.inner,
-    //         state: &mut self.vdom_state,
-    //     };
-    //     self.vdom.render_immediate(&mut writer);
+    /// Build the data passed to Dioxus listeners for an event
+    fn event_data(&self, event: &RendererEvent) -> Rc<dyn std::any::Any> {
+        let (client_x, client_y) = client_position(&self.inner, event.target);
+        // Events are positioned in the viewport, which may have been scrolled down the page
+        let scroll = self.inner.viewport_scroll() as f32;
+        let data = match event.data {
+            EventData::Click { x, y } | EventData::MouseMove { x, y } => BlitzMouseData {
+                client: (x as f64, y as f64),
+                page: (x as f64, (y + scroll) as f64),
+                element: ((x - client_x) as f64, (y - client_y) as f64),
+                trigger_button: match event.data {
+                    EventData::Click { .. } => Some(MouseButton::Primary),
+                    EventData::MouseMove { .. } => None,
+                },
+            },
+        };
 
-    //     println!("APPLY MUTATIONS");
-    //     self.inner.print_tree();
-    // }
+        Rc::new(PlatformEventData::new(Box::new(data)))
+    }
+
+    /// Diff the VirtualDom and apply the resulting mutations to the document
+    pub fn apply_mutations(&mut self) {
+        let mut writer = MutationWriter {
+            doc: &mut self.inner,
+            state: &mut self.vdom_state,
+        };
+        self.vdom.render_immediate(&mut writer);
+    }
 }
 
 /// The state of the Dioxus integration with the RealDom
@@ -117,6 +186,8 @@ pub struct DioxusState {
     stack: Vec<NodeId>,
     /// Mapping from vdom ElementId -> rdom NodeId
     node_id_mapping: Vec<Option<NodeId>>,
+    /// The names of the events each vdom ElementId is listening for
+    listeners: FxHashMap<ElementId, FxHashSet<&'static str>>,
 }
 
 /// A writer for mutations that can be used with the RealDom.
@@ -137,6 +208,7 @@ impl DioxusState {
             templates: FxHashMap::default(),
             stack: vec![root_id],
             node_id_mapping: vec![Some(root_id)],
+            listeners: FxHashMap::default(),
         })
     }
 
@@ -150,6 +222,14 @@ impl DioxusState {
         self.node_id_mapping.get(element_id.0).copied().flatten()
     }
 
+    /// Whether the element has registered a listener for the named event
+    pub fn has_listener(&self, element_id: ElementId, name: &str) -> bool {
+        self.listeners
+            .get(&element_id)
+            .map(|names| names.contains(name))
+            .unwrap_or(false)
+    }
+
     // /// Create a mutation writer for the RealDom
     // pub fn create_mutation_writer<'a>(&'a mut self, doc: &'a mut Document) -> MutationWriter<'a> {
     //     MutationWriter { doc, state: self }
@@ -172,6 +252,22 @@ impl MutationWriter<'_> {
 
         // Set the new mapping
         self.state.node_id_mapping[element_id] = Some(node_id);
+
+        // Tag elements with their ElementId so that events can be mapped back to the vdom
+        if let Some(element) = self.doc.get_node_mut(node_id).unwrap().element_data_mut() {
+            let value = element_id.to_string();
+            match element
+                .attrs
+                .iter_mut()
+                .find(|attr| attr.name.local == *DIOXUS_ID_ATTR)
+            {
+                Some(attr) => attr.value = value,
+                None => element.attrs.push(Attribute {
+                    name: attr_qual_name(DIOXUS_ID_ATTR, None),
+                    value,
+                }),
+            }
+        }
     }
 
     /// Find a child in the document by child index path
@@ -207,8 +303,13 @@ impl WriteMutations for MutationWriter<'_> {
         let children = self.state.stack.split_off(self.state.stack.len() - m);
         let parent = self.state.element_to_node_id(id);
         for child in children {
+            let child_idx = self.doc.get_node(parent).unwrap().children.len();
             self.doc.get_node_mut(parent).unwrap().children.push(child);
+            let child_node = self.doc.get_node_mut(child).unwrap();
+            child_node.parent = Some(parent);
+            child_node.child_idx = child_idx;
         }
+        self.doc.mark_children_changed(parent);
     }
 
     fn assign_node_id(&mut self, path: &'static [u8], id: ElementId) {
@@ -251,6 +352,7 @@ impl WriteMutations for MutationWriter<'_> {
                 content: value.to_string(),
             });
         }
+        self.doc.invalidate_layout(node_id);
     }
 
     fn load_template(&mut self, name: &'static str, index: usize, id: ElementId) {
@@ -311,6 +413,8 @@ impl WriteMutations for MutationWriter<'_> {
         id: ElementId,
     ) {
         let node_id = self.state.element_to_node_id(id);
+        let guard = self.doc.guard().clone();
+        self.doc.snapshot_attribute_change(node_id, name);
         let node = self.doc.get_node_mut(node_id).unwrap();
         if let NodeData::Element(ref mut element) = node.raw_dom_data {
             // FIXME: support non-text attributes
@@ -324,7 +428,7 @@ impl WriteMutations for MutationWriter<'_> {
                     existing_attr.value = val.to_string();
                 } else {
                     element.attrs.push(Attribute {
-                        name: qual_name(name, ns),
+                        name: attr_qual_name(name, ns),
                         value: val.to_string(),
                     });
                 }
@@ -334,6 +438,10 @@ impl WriteMutations for MutationWriter<'_> {
                 // FIXME: check namespace
                 element.attrs.retain(|attr| attr.name.local != *name);
             }
+
+            if name == "style" {
+                element.flush_style_attribute(&guard);
+            }
         }
     }
 
@@ -343,18 +451,17 @@ impl WriteMutations for MutationWriter<'_> {
         if let NodeData::Text(ref mut text) = node.raw_dom_data {
             text.content = value.to_string();
         }
+        self.doc.invalidate_layout(node_id);
     }
 
-    fn create_event_listener(&mut self, _name: &'static str, _id: ElementId) {
-        // let node_id = self.state.element_to_node_id(id);
-        // let mut node = self.rdom.get_mut(node_id).unwrap();
-        // node.add_event_listener(name);
+    fn create_event_listener(&mut self, name: &'static str, id: ElementId) {
+        self.state.listeners.entry(id).or_default().insert(name);
     }
 
-    fn remove_event_listener(&mut self, _name: &'static str, _id: ElementId) {
-        // let node_id = self.state.element_to_node_id(id);
-        // let mut node = self.rdom.get_mut(node_id).unwrap();
-        // node.remove_event_listener(name);
+    fn remove_event_listener(&mut self, name: &'static str, id: ElementId) {
+        if let Some(names) = self.state.listeners.get_mut(&id) {
+            names.remove(name);
+        }
     }
 
     fn remove_node(&mut self, id: ElementId) {
@@ -370,6 +477,40 @@ impl WriteMutations for MutationWriter<'_> {
     }
 }
 
+/// Read the ElementId that an element was tagged with when it was mounted
+fn dioxus_id(element: &ElementNodeData) -> Option<ElementId> {
+    element
+        .attrs()
+        .iter()
+        .find(|attr| attr.name.local == *DIOXUS_ID_ATTR)
+        .and_then(|attr| attr.value.parse().ok())
+        .map(ElementId)
+}
+
+/// Position of a node's border box relative to the top-left corner of the viewport, offset the same way as in
+/// `Node::hit`: by sticky positioning, and by the scrolling of its scroll containers and the page
+fn client_position(doc: &Document, node_id: NodeId) -> (f32, f32) {
+    let mut x = 0.0;
+    let mut y = 0.0;
+    let mut next = Some(node_id);
+    while let Some(id) = next {
+        let node = &doc.tree()[id];
+        x += node.final_layout.location.x + node.sticky_offset.x;
+        y += node.final_layout.location.y + node.sticky_offset.y;
+        // The contents of scroll containers are moved by how far they've been scrolled
+        if id != node_id {
+            x -= node.scroll_offset.x;
+            y -= node.scroll_offset.y;
+        }
+        // Fixed elements are already positioned against the viewport, and don't scroll with the page
+        if node.is_fixed() {
+            return (x, y);
+        }
+        next = node.layout_parent.or(node.parent);
+    }
+    (x, y - doc.viewport_scroll() as f32)
+}
+
 fn create_template_node(doc: &mut Document, node: &TemplateNode) -> NodeId {
     match node {
         TemplateNode::Element {
@@ -393,7 +534,7 @@ fn create_template_node(doc: &mut Document, node: &TemplateNode) -> NodeId {
                             value,
                             namespace,
                         } => Some(Attribute {
-                            name: qual_name(*name, *namespace),
+                            name: attr_qual_name(*name, *namespace),
                             value: value.to_string(),
                         }),
                         TemplateAttribute::Dynamic { .. } => None,
@@ -434,3 +575,52 @@ fn create_template_node(doc: &mut Document, node: &TemplateNode) -> NodeId {
         TemplateNode::DynamicText { .. } => doc.create_text_node(""),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blitz_dom::local_name;
+    use dioxus::prelude::*;
+
+    fn app() -> Element {
+        let state = "closed";
+        rsx! {
+            div { id: "static", "data-state": "open" }
+            div { id: "dynamic", "data-state": "{state}" }
+        }
+    }
+
+    fn node_by_id<'a>(doc: &'a DioxusDocument, id: &str) -> &'a blitz_dom::Node {
+        doc.inner
+            .tree()
+            .iter()
+            .map(|(_, node)| node)
+            .find(|node| node.attr(local_name!("id")) == Some(id))
+            .unwrap()
+    }
+
+    #[test]
+    fn attribute_selectors_match_dioxus_attributes() {
+        let mut doc = DioxusDocument::new(VirtualDom::new(app));
+        doc.inner.add_stylesheet(
+            r#"
+            [data-state="open"], [data-state="closed"] { display: none }
+            [data-dioxus-id] { order: 1 }
+            "#,
+        );
+        doc.inner.resolve();
+
+        // Static attributes come from the template, dynamic ones from set_attribute
+        for id in ["static", "dynamic"] {
+            let styles = node_by_id(&doc, id).primary_styles().unwrap();
+            assert_eq!(
+                styles.clone_display(),
+                style::values::computed::Display::None
+            );
+        }
+
+        // Elements with dynamic attributes are tagged with their ElementId
+        let styles = node_by_id(&doc, "dynamic").primary_styles().unwrap();
+        assert_eq!(styles.get_position().order, 1);
+    }
+}
@@ -0,0 +1,370 @@
+//! Conversion of Blitz renderer events into Dioxus event data
+
+use std::collections::HashMap;
+
+use dioxus::html::{
+    geometry::{ClientPoint, ElementPoint, PagePoint, ScreenPoint, WheelDelta},
+    input_data::{
+        keyboard_types::{Code, Key, Location},
+        MouseButton, MouseButtonSet,
+    },
+    point_interaction::{
+        InteractionElementOffset, InteractionLocation, ModifiersInteraction, PointerInteraction,
+    },
+    AnimationData, ClipboardData, CompositionData, DragData, FocusData, FormData, FormValue,
+    HasAnimationData, HasClipboardData, HasCompositionData, HasDragData, HasFocusData, HasFormData,
+    HasImageData, HasKeyboardData, HasMediaData, HasMouseData, HasPointerData, HasScrollData,
+    HasSelectionData, HasToggleData, HasTouchData, HasTransitionData, HasWheelData,
+    HtmlEventConverter, ImageData, KeyboardData, MediaData, Modifiers, MountedData, MouseData,
+    PlatformEventData, PointerData, ScrollData, SelectionData, ToggleData, TouchData, TouchPoint,
+    TransitionData, WheelData,
+};
+
+/// Mouse event data produced from a hit-tested Blitz event
+#[derive(Clone, Debug, Default)]
+pub struct BlitzMouseData {
+    /// Position relative to the viewport
+    pub client: (f64, f64),
+    /// Position relative to the document
+    pub page: (f64, f64),
+    /// Position relative to the target element's border box
+    pub element: (f64, f64),
+    pub trigger_button: Option<MouseButton>,
+}
+
+impl InteractionLocation for BlitzMouseData {
+    fn client_coordinates(&self) -> ClientPoint {
+        ClientPoint::new(self.client.0, self.client.1)
+    }
+
+    fn screen_coordinates(&self) -> ScreenPoint {
+        // We don't know where the window is on the screen, so this is the best we can do
+        ScreenPoint::new(self.client.0, self.client.1)
+    }
+
+    fn page_coordinates(&self) -> PagePoint {
+        PagePoint::new(self.page.0, self.page.1)
+    }
+}
+
+impl InteractionElementOffset for BlitzMouseData {
+    fn element_coordinates(&self) -> ElementPoint {
+        ElementPoint::new(self.element.0, self.element.1)
+    }
+}
+
+impl ModifiersInteraction for BlitzMouseData {
+    fn modifiers(&self) -> Modifiers {
+        // TODO: thread keyboard modifiers through from the window
+        Modifiers::empty()
+    }
+}
+
+impl PointerInteraction for BlitzMouseData {
+    fn trigger_button(&self) -> Option<MouseButton> {
+        self.trigger_button
+    }
+
+    fn held_buttons(&self) -> MouseButtonSet {
+        MouseButtonSet::empty()
+    }
+}
+
+impl HasMouseData for BlitzMouseData {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasDragData for BlitzMouseData {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasPointerData for BlitzMouseData {
+    fn pointer_id(&self) -> i32 {
+        0
+    }
+
+    fn width(&self) -> i32 {
+        1
+    }
+
+    fn height(&self) -> i32 {
+        1
+    }
+
+    fn pressure(&self) -> f32 {
+        0.0
+    }
+
+    fn tangential_pressure(&self) -> f32 {
+        0.0
+    }
+
+    fn tilt_x(&self) -> i32 {
+        0
+    }
+
+    fn tilt_y(&self) -> i32 {
+        0
+    }
+
+    fn twist(&self) -> i32 {
+        0
+    }
+
+    fn pointer_type(&self) -> String {
+        "mouse".to_string()
+    }
+
+    fn is_primary(&self) -> bool {
+        true
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasWheelData for BlitzMouseData {
+    fn delta(&self) -> WheelDelta {
+        WheelDelta::pixels(0.0, 0.0, 0.0)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+/// Neutral data for the event types Blitz doesn't produce (yet), so that a listener for one of them receiving an event
+/// sees empty data rather than taking down the app
+#[derive(Clone, Debug, Default)]
+pub struct BlitzEmptyData;
+
+impl ModifiersInteraction for BlitzEmptyData {
+    fn modifiers(&self) -> Modifiers {
+        Modifiers::empty()
+    }
+}
+
+impl HasAnimationData for BlitzEmptyData {
+    fn animation_name(&self) -> String {
+        String::new()
+    }
+
+    fn pseudo_element(&self) -> String {
+        String::new()
+    }
+
+    fn elapsed_time(&self) -> f32 {
+        0.0
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasCompositionData for BlitzEmptyData {
+    fn data(&self) -> String {
+        String::new()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasFormData for BlitzEmptyData {
+    fn value(&self) -> String {
+        String::new()
+    }
+
+    fn values(&self) -> HashMap<String, FormValue> {
+        HashMap::new()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasImageData for BlitzEmptyData {
+    fn load_error(&self) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasKeyboardData for BlitzEmptyData {
+    fn key(&self) -> Key {
+        Key::Unidentified
+    }
+
+    fn code(&self) -> Code {
+        Code::Unidentified
+    }
+
+    fn location(&self) -> Location {
+        Location::Standard
+    }
+
+    fn is_auto_repeating(&self) -> bool {
+        false
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasTouchData for BlitzEmptyData {
+    fn touches(&self) -> Vec<TouchPoint> {
+        Vec::new()
+    }
+
+    fn touches_changed(&self) -> Vec<TouchPoint> {
+        Vec::new()
+    }
+
+    fn target_touches(&self) -> Vec<TouchPoint> {
+        Vec::new()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+impl HasTransitionData for BlitzEmptyData {
+    fn property_name(&self) -> String {
+        String::new()
+    }
+
+    fn pseudo_element(&self) -> String {
+        String::new()
+    }
+
+    fn elapsed_time(&self) -> f32 {
+        0.0
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self as &dyn std::any::Any
+    }
+}
+
+/// Event types that carry no data besides the event itself
+macro_rules! impl_empty_event_data {
+    ($($trait:ident),*) => {
+        $(
+            impl $trait for BlitzEmptyData {
+                fn as_any(&self) -> &dyn std::any::Any {
+                    self as &dyn std::any::Any
+                }
+            }
+        )*
+    };
+}
+
+impl_empty_event_data!(
+    HasClipboardData,
+    HasFocusData,
+    HasMediaData,
+    HasScrollData,
+    HasSelectionData,
+    HasToggleData
+);
+
+/// Read mouse-like event data, falling back to an event at the origin if the event wasn't produced from a mouse
+fn mouse_data(event: &PlatformEventData) -> BlitzMouseData {
+    event
+        .downcast::<BlitzMouseData>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Converts the platform event data we hand to the VirtualDom into the event types that listeners receive
+///
+/// Mouse, drag, pointer and wheel events carry [`BlitzMouseData`]. The other event types aren't produced by Blitz yet,
+/// so they carry [`BlitzEmptyData`].
+pub struct BlitzEventConverter;
+
+impl HtmlEventConverter for BlitzEventConverter {
+    fn convert_animation_data(&self, _event: &PlatformEventData) -> AnimationData {
+        AnimationData::new(BlitzEmptyData)
+    }
+
+    fn convert_clipboard_data(&self, _event: &PlatformEventData) -> ClipboardData {
+        ClipboardData::new(BlitzEmptyData)
+    }
+
+    fn convert_composition_data(&self, _event: &PlatformEventData) -> CompositionData {
+        CompositionData::new(BlitzEmptyData)
+    }
+
+    fn convert_drag_data(&self, event: &PlatformEventData) -> DragData {
+        DragData::new(mouse_data(event))
+    }
+
+    fn convert_focus_data(&self, _event: &PlatformEventData) -> FocusData {
+        FocusData::new(BlitzEmptyData)
+    }
+
+    fn convert_form_data(&self, _event: &PlatformEventData) -> FormData {
+        FormData::new(BlitzEmptyData)
+    }
+
+    fn convert_image_data(&self, _event: &PlatformEventData) -> ImageData {
+        ImageData::new(BlitzEmptyData)
+    }
+
+    fn convert_keyboard_data(&self, _event: &PlatformEventData) -> KeyboardData {
+        KeyboardData::new(BlitzEmptyData)
+    }
+
+    fn convert_media_data(&self, _event: &PlatformEventData) -> MediaData {
+        MediaData::new(BlitzEmptyData)
+    }
+
+    fn convert_mounted_data(&self, _event: &PlatformEventData) -> MountedData {
+        MountedData::new(())
+    }
+
+    fn convert_mouse_data(&self, event: &PlatformEventData) -> MouseData {
+        mouse_data(event).into()
+    }
+
+    fn convert_pointer_data(&self, event: &PlatformEventData) -> PointerData {
+        PointerData::new(mouse_data(event))
+    }
+
+    fn convert_scroll_data(&self, _event: &PlatformEventData) -> ScrollData {
+        ScrollData::new(BlitzEmptyData)
+    }
+
+    fn convert_selection_data(&self, _event: &PlatformEventData) -> SelectionData {
+        SelectionData::new(BlitzEmptyData)
+    }
+
+    fn convert_toggle_data(&self, _event: &PlatformEventData) -> ToggleData {
+        ToggleData::new(BlitzEmptyData)
+    }
+
+    fn convert_touch_data(&self, _event: &PlatformEventData) -> TouchData {
+        TouchData::new(BlitzEmptyData)
+    }
+
+    fn convert_transition_data(&self, _event: &PlatformEventData) -> TransitionData {
+        TransitionData::new(BlitzEmptyData)
+    }
+
+    fn convert_wheel_data(&self, event: &PlatformEventData) -> WheelData {
+        WheelData::new(mouse_data(event))
+    }
+}
@@ -36,6 +36,11 @@ impl HtmlDocument {
             dom.set_base_url(&url);
         }
 
+        // Fetch resources through the configured provider rather than over HTTP
+        if let Some(provider) = &cfg.net_provider {
+            dom.set_net_provider(provider.clone());
+        }
+
         // Include default and user-specified stylesheets
         dom.add_stylesheet(include_str!("./default.css"));
         for ss in &cfg.stylesheets {
@@ -1,4 +1,5 @@
 mod dioxus_document;
+mod events;
 mod html_document;
 
 pub(crate) use dioxus_document::DioxusDocument;
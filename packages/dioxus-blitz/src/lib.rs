@@ -6,11 +6,13 @@ use crate::documents::HtmlDocument;
 use crate::waker::{EventData, UserWindowEvent};
 
 use blitz::RenderState;
+use blitz_dom::net::{FileProvider, HttpProvider, NetProvider, ProviderChain};
 use blitz_dom::DocumentLike;
 use dioxus::prelude::*;
 use documents::DioxusDocument;
 use muda::{MenuEvent, MenuId};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tao::event_loop::EventLoopBuilder;
 use tao::window::WindowId;
 use tao::{
@@ -23,6 +25,8 @@ use url::Url;
 pub struct Config {
     pub stylesheets: Vec<String>,
     pub base_url: Option<String>,
+    /// Where to fetch images, stylesheets and fonts from. Defaults to HTTP.
+    pub net_provider: Option<Arc<dyn NetProvider>>,
 }
 
 /// Launch an interactive HTML/CSS renderer driven by the Dioxus virtualdom
@@ -38,38 +42,51 @@ pub fn launch_cfg(root: fn() -> Element, cfg: Config) {
 pub fn launch_cfg_with_props<P: Clone + 'static, M: 'static>(
     root: impl ComponentFunction<P, M>,
     props: P,
-    _cfg: Config,
+    cfg: Config,
 ) {
-    // Spin up the virtualdom
-    // We're going to need to hit it with a special waker
-    let vdom = VirtualDom::new_with_props(root, props);
-    // vdom.rebuild_in_place();
-    let document = DioxusDocument::new(vdom);
-    // document.apply_mutations();
-    let window = crate::window::View::new(document);
-    launch_with_window(window)
+    launch_with_document(move || {
+        // Spin up the virtualdom
+        // We're going to need to hit it with a special waker
+        let vdom = VirtualDom::new_with_props(root, props);
+        // vdom.rebuild_in_place();
+        let mut document = DioxusDocument::new(vdom);
+        if let Some(provider) = cfg.net_provider {
+            document.as_mut().set_net_provider(provider);
+        }
+        document
+    })
 }
 
 pub fn launch_url(url: &str) {
-    const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:60.0) Gecko/20100101 Firefox/81.0";
     println!("{}", url);
 
-    // Assert that url is valid
-    let url = url.to_owned();
-    Url::parse(&url).expect("Invalid url");
-
-    let html = ureq::get(&url)
-        .set("User-Agent", USER_AGENT)
-        .call()
-        .unwrap()
-        .into_string()
-        .unwrap();
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(err) => {
+            eprintln!("Invalid url {}: {}", url, err);
+            return;
+        }
+    };
+
+    // Fetch the page the same way the document will fetch its resources. Responses are cached on disk so that
+    // reopening the same page doesn't download it all again.
+    let http = HttpProvider::default().with_cache(std::env::temp_dir().join("blitz-http-cache"));
+    let provider = Arc::new(ProviderChain(vec![Arc::new(FileProvider), Arc::new(http)]));
+    let html = match provider.fetch(&url) {
+        // Invalid bytes are replaced rather than refusing to show the page
+        Ok(response) => String::from_utf8_lossy(&response.bytes).into_owned(),
+        Err(err) => {
+            eprintln!("Error fetching {}: {}", url, err);
+            return;
+        }
+    };
 
     launch_static_html_cfg(
         &html,
         Config {
             stylesheets: Vec::new(),
-            base_url: Some(url),
+            base_url: Some(url.to_string()),
+            net_provider: Some(provider),
         },
     )
 }
@@ -79,12 +96,10 @@ pub fn launch_static_html(html: &str) {
 }
 
 pub fn launch_static_html_cfg(html: &str, cfg: Config) {
-    let document = HtmlDocument::from_html(html, &cfg);
-    let window = crate::window::View::new(document);
-    launch_with_window(window)
+    launch_with_document(|| HtmlDocument::from_html(html, &cfg))
 }
 
-fn launch_with_window<Doc: DocumentLike + 'static>(window: crate::window::View<'static, Doc>) {
+fn launch_with_document<Doc: DocumentLike + 'static>(build_document: impl FnOnce() -> Doc) {
     // Turn on the runtime and enter it
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -93,6 +108,9 @@ fn launch_with_window<Doc: DocumentLike + 'static>(window: crate::window::View<'
 
     let _guard = rt.enter();
 
+    // Build the document inside the runtime so that it can start fetching images and stylesheets straight away
+    let window = crate::window::View::new(build_document());
+
     // Build an event loop for the application
     let event_loop = EventLoopBuilder::<UserWindowEvent>::with_user_event().build();
     let proxy = event_loop.create_proxy();
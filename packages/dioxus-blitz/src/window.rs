@@ -46,7 +46,9 @@ impl<'a, Doc: DocumentLike> View<'a, Doc> {
             None => {}
             Some(waker) => {
                 let cx = std::task::Context::from_waker(waker);
-                self.renderer.poll(cx);
+                if self.renderer.poll(cx) {
+                    self.request_redraw();
+                }
             }
         }
     }
@@ -68,8 +70,19 @@ impl<'a, Doc: DocumentLike> View<'a, Doc> {
                 // modifiers,
                 ..
             } => {
-                if state == ElementState::Pressed && button == MouseButton::Left {
-                    self.renderer.click()
+                if button == MouseButton::Left {
+                    let changed = match state {
+                        ElementState::Pressed => {
+                            let changed = self.renderer.mouse_down();
+                            self.renderer.click();
+                            changed
+                        }
+                        ElementState::Released => self.renderer.mouse_up(),
+                        _ => false,
+                    };
+                    if changed {
+                        self.request_redraw();
+                    }
                 }
             }
 
@@ -158,7 +171,11 @@ impl<'a, Doc: DocumentLike> View<'a, Doc> {
                 }
             }
             WindowEvent::CursorEntered { /*device_id*/.. } => {}
-            WindowEvent::CursorLeft { /*device_id*/.. } => {}
+            WindowEvent::CursorLeft { /*device_id*/.. } => {
+                if self.renderer.mouse_leave() {
+                    self.request_redraw();
+                }
+            }
             WindowEvent::MouseWheel {
                 // device_id,
                 delta,
@@ -167,11 +184,11 @@ impl<'a, Doc: DocumentLike> View<'a, Doc> {
                 ..
             } => {
                 match delta {
-                    tao::event::MouseScrollDelta::LineDelta(_, y) => {
-                        self.renderer.scroll_by(y as f64 * 20.0)
+                    tao::event::MouseScrollDelta::LineDelta(x, y) => {
+                        self.renderer.scroll_by(x as f64 * 20.0, y as f64 * 20.0)
                     }
                     tao::event::MouseScrollDelta::PixelDelta(offsets) => {
-                        self.renderer.scroll_by(offsets.y)
+                        self.renderer.scroll_by(offsets.x, offsets.y)
                     }
                     _ => {}
                 };
@@ -1,22 +1,39 @@
+use crate::events::RendererEvent;
+use crate::net::{NetProvider, Resource, ResourceError, ResourceLoader};
 use crate::node::DisplayOuter;
+use crate::text::{FontContext, FontFaceDescriptors};
 use crate::{Node, NodeData, TextNodeData};
 use selectors::{matching::QuirksMode, Element};
 use slab::Slab;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicIsize};
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use style::servo_arc::Arc as ServoArc;
 use style::{
     dom::{TDocument, TNode},
+    font_face::Source,
     media_queries::{Device, MediaList},
-    selector_parser::SnapshotMap,
+    selector_parser::{ServoElementSnapshot, SnapshotMap},
     shared_lock::{SharedRwLock, StylesheetGuards},
-    stylesheets::{AllowImportRules, DocumentStyleSheet, Origin, Stylesheet, UrlExtraData},
+    stylesheets::{
+        AllowImportRules, CssRule, DocumentStyleSheet, Origin, Stylesheet, StylesheetInDocument,
+        UrlExtraData,
+    },
     stylist::Stylist,
 };
-use taffy::{AvailableSpace, Cache, Layout};
+use style_traits::dom::ElementState;
+use taffy::{AvailableSpace, Cache, Layout, Point};
 use url::Url;
 
 pub trait DocumentLike: AsRef<Document> + AsMut<Document> + Into<Document> {
-    fn poll(&mut self, _cx: std::task::Context) {
+    /// Process any pending work. Returns true if the document changed and needs to be redrawn
+    fn poll(&mut self, mut cx: std::task::Context) -> bool {
+        // Default implementation only applies resources that have finished loading
+        self.as_mut().poll_resources(&mut cx)
+    }
+
+    fn handle_event(&mut self, _event: RendererEvent) {
         // Default implementation does nothing
     }
 }
@@ -36,14 +53,43 @@ pub struct Document {
 
     /// The styling engine of firefox
     pub(crate) stylist: Stylist,
+    /// The stylist's stylesheets in the order they cascade, along with the `<style>` or `<link>` element that each
+    /// came from. Sheets from elements are kept in document order however they arrive.
+    pub(crate) stylesheets: Vec<(Option<usize>, DocumentStyleSheet)>,
 
     // caching for the stylist
     pub(crate) snapshots: SnapshotMap,
+    /// Snapshots of elements whose state or attributes changed since the last restyle, keyed by node id. They're
+    /// moved into `snapshots` when we restyle, which is keyed by the nodes' addresses.
+    pub(crate) pending_snapshots: HashMap<usize, ServoElementSnapshot>,
 
     pub(crate) nodes_to_id: HashMap<String, usize>,
 
     /// Base url for resolving linked resources (stylesheets, images, fonts, etc)
     pub(crate) base_url: Option<url::Url>,
+
+    /// Fonts used to shape text during layout, and to draw the shaped glyphs
+    pub(crate) fonts: FontContext,
+
+    /// Images and stylesheets that are still being fetched
+    pub(crate) loader: ResourceLoader,
+
+    /// Resources that failed to load, in the order they failed. Like a browser's console, these are collected but
+    /// don't stop the rest of the document from loading.
+    pub(crate) errors: Vec<ResourceError>,
+
+    /// The node under the pointer
+    pub(crate) hover_node_id: Option<usize>,
+    /// The node the pointer was pressed on, while it's held down
+    pub(crate) active_node_id: Option<usize>,
+    pub(crate) focus_node_id: Option<usize>,
+
+    /// How far the page has been scrolled down, in CSS pixels
+    pub(crate) viewport_scroll: f64,
+    /// Elements with `position: fixed` and `position: sticky`, in tree order. Layout moves them after the rest of the
+    /// tree has been laid out.
+    pub(crate) fixed_nodes: Vec<usize>,
+    pub(crate) sticky_nodes: Vec<usize>,
 }
 
 impl Document {
@@ -59,14 +105,29 @@ impl Document {
         style_config::set_bool("layout.flexbox.enabled", true);
         style_config::set_bool("layout.legacy_layout", true);
         style_config::set_bool("layout.columns.enabled", true);
+        style_config::set_bool("layout.grid.enabled", true);
+        // Let stylo size its style thread pool from the number of cores. This has to happen before the pool is first
+        // used, which is when the first document is styled.
+        style_config::set_i32("layout.threads", -1);
 
         let mut doc = Self {
             guard,
             nodes,
             stylist,
+            stylesheets: Vec::new(),
             snapshots,
+            pending_snapshots: HashMap::new(),
             nodes_to_id,
             base_url: None,
+            fonts: FontContext::default(),
+            loader: ResourceLoader::default(),
+            errors: Vec::new(),
+            hover_node_id: None,
+            active_node_id: None,
+            focus_node_id: None,
+            viewport_scroll: 0.0,
+            fixed_nodes: Vec::new(),
+            sticky_nodes: Vec::new(),
         };
 
         // Initialise document with root Document node
@@ -75,15 +136,72 @@ impl Document {
         doc
     }
 
-    /// Set base url for resolving linked resources (stylesheets, images, fonts, etc)
+    /// Parse and style a document in an 800x600 viewport
+    #[cfg(test)]
+    pub(crate) fn from_html_for_test(html: &str) -> Self {
+        use euclid::{Scale, Size2D};
+        use style::media_queries::MediaType;
+
+        let device = Device::new(
+            MediaType::screen(),
+            QuirksMode::NoQuirks,
+            Size2D::new(800.0, 600.0),
+            Scale::new(1.0),
+        );
+        let mut doc = Self::new(device);
+        crate::DocumentHtmlParser::parse_into_doc(&mut doc, html);
+        doc.resolve();
+        doc
+    }
+
+    /// The node id of the element with an id attribute
+    #[cfg(test)]
+    pub(crate) fn element_by_id_for_test(&self, id: &str) -> usize {
+        let (node_id, _) = self
+            .nodes
+            .iter()
+            .find(|(_, node)| node.attr(html5ever::local_name!("id")) == Some(id))
+            .unwrap();
+        node_id
+    }
+
+    /// Set base url for resolving linked resources (stylesheets, images, fonts, etc). A local path can be given
+    /// instead of a `file://` url.
     pub fn set_base_url(&mut self, url: &str) {
-        self.base_url = Url::parse(url).ok();
+        let url = Url::parse(url).ok().or_else(|| {
+            let path = std::fs::canonicalize(url).ok()?;
+            Url::from_file_path(path).ok()
+        });
+
+        // Relative urls only resolve inside a directory if it ends with a slash, which is easy to leave off
+        self.base_url = url.map(|url| match url.to_file_path() {
+            Ok(path) if path.is_dir() => Url::from_directory_path(path).unwrap_or(url),
+            _ => url,
+        });
+    }
+
+    /// Set where the document fetches its images, stylesheets and fonts from (local files and HTTP by default)
+    pub fn set_net_provider(&mut self, provider: Arc<dyn NetProvider>) {
+        self.loader.provider = provider;
+    }
+
+    /// Resources that failed to load
+    pub fn errors(&self) -> &[ResourceError] {
+        &self.errors
+    }
+
+    pub(crate) fn report_error(&mut self, error: ResourceError) {
+        self.errors.push(error);
     }
 
     pub fn guard(&self) -> &SharedRwLock {
         &self.guard
     }
 
+    pub fn fonts(&self) -> &FontContext {
+        &self.fonts
+    }
+
     pub fn tree(&self) -> &Slab<Node> {
         &self.nodes
     }
@@ -119,17 +237,34 @@ impl Document {
             parent: None,
             children: vec![],
             child_idx: 0,
+            before: None,
+            after: None,
+            anonymous_blocks: vec![],
+            layout_children: vec![],
+            layout_parent: None,
 
             raw_dom_data: node_data,
             stylo_element_data: Default::default(),
             guard: self.guard.clone(),
+            element_state: ElementState::empty(),
+            // New nodes haven't been styled, so the traversal has to visit them and their children
+            dirty_descendants: AtomicBool::new(true),
+            has_snapshot: AtomicBool::new(false),
+            snapshot_handled: AtomicBool::new(false),
+            children_to_process: AtomicIsize::new(0),
 
             style: Default::default(),
             hidden: false,
             display_outer: DisplayOuter::Block,
+            is_inline_root: false,
+            has_calc: false,
+            text_layout: None,
             cache: Cache::new(),
             unrounded_layout: Layout::new(),
             final_layout: Layout::new(),
+            static_position: Point::ZERO,
+            sticky_offset: Point::ZERO,
+            scroll_offset: Point::ZERO,
         });
 
         id
@@ -190,6 +325,7 @@ impl Document {
         }
 
         self.nodes[parent_id].children = children;
+        self.mark_children_changed(parent_id);
     }
 
     pub fn append(&mut self, node_id: usize, appended_node_ids: &[usize]) {
@@ -213,30 +349,57 @@ impl Document {
         }
 
         self.nodes[parent_id].children = children;
+        self.mark_children_changed(parent_id);
     }
 
-    pub fn remove_node(&mut self, node_id: usize) -> Option<Node> {
-        fn remove_node_ignoring_parent(doc: &mut Document, node_id: usize) -> Option<Node> {
-            let node = doc.nodes.try_remove(node_id);
-            if let Some(node) = &node {
-                for &child in &node.children {
-                    remove_node_ignoring_parent(doc, child);
-                }
+    /// Restyle a node whose children were added or removed, along with its children, since that can change which
+    /// selectors match them (e.g. `:empty` or `:first-child`)
+    pub fn mark_children_changed(&mut self, node_id: usize) {
+        self.nodes[node_id].mark_for_restyle();
+        self.invalidate_layout(node_id);
+    }
+
+    /// Remove a node and its descendants (including generated content) from the tree without detaching it from its
+    /// parent
+    pub(crate) fn remove_subtree(&mut self, node_id: usize) -> Option<Node> {
+        let node = self.nodes.try_remove(node_id);
+        self.pending_snapshots.remove(&node_id);
+        self.remove_stylesheets(node_id);
+        self.fixed_nodes.retain(|id| *id != node_id);
+        self.sticky_nodes.retain(|id| *id != node_id);
+        if let Some(node) = &node {
+            let generated = node
+                .before
+                .iter()
+                .chain(&node.after)
+                .chain(&node.anonymous_blocks);
+            for &child in node.children.iter().chain(generated) {
+                self.remove_subtree(child);
             }
-            node
         }
+        node
+    }
 
-        let node = remove_node_ignoring_parent(self, node_id);
+    pub fn remove_node(&mut self, node_id: usize) -> Option<Node> {
+        let node = self.remove_subtree(node_id);
 
         // Update child_idx values
         if let Some(Node {
             mut child_idx,
             parent: Some(parent_id),
+            layout_parent,
             ..
         }) = node
         {
+            // The node may have been laid out inside one of the parent's anonymous blocks
+            if let Some(layout_parent) = layout_parent.and_then(|id| self.nodes.get_mut(id)) {
+                layout_parent.layout_children.retain(|id| *id != node_id);
+            }
+
             let parent = &mut self.nodes[parent_id];
 
+            parent.layout_children.retain(|id| *id != node_id);
+
             let mut children = std::mem::replace(&mut parent.children, Vec::new());
             children.remove(child_idx);
 
@@ -249,16 +412,18 @@ impl Document {
             }
 
             self.nodes[parent_id].children = children;
+            self.mark_children_changed(parent_id);
         }
 
         node
     }
 
-    pub fn resolve_url(&self, raw: &str) -> url::Url {
-        match &self.base_url {
-            Some(base_url) => base_url.join(raw).unwrap(),
-            None => url::Url::parse(raw).unwrap(),
-        }
+    pub fn resolve_url(&self, raw: &str) -> Result<Url, ResourceError> {
+        let url = match &self.base_url {
+            Some(base_url) => base_url.join(raw),
+            None => Url::parse(raw),
+        };
+        url.map_err(|err| ResourceError::InvalidUrl(raw.to_string(), err))
     }
 
     pub fn flush_child_indexes(&mut self, target_id: usize, child_idx: usize, level: usize) {
@@ -279,17 +444,26 @@ impl Document {
     pub fn process_style_element(&mut self, target_id: usize) {
         let css = self.nodes[target_id].text_content();
         let css = html_escape::decode_html_entities(&css);
-        self.add_stylesheet(&css);
+        self.insert_stylesheet(Some(target_id), &css);
     }
 
+    /// Add a stylesheet that isn't in the document (e.g. a default stylesheet). It cascades after every stylesheet
+    /// added so far.
     pub fn add_stylesheet(&mut self, css: &str) {
+        self.insert_stylesheet(None, css);
+    }
+
+    /// Add a stylesheet from `owner`'s `<style>` or `<link>` element, or from outside the document if there's no owner
+    fn insert_stylesheet(&mut self, owner: Option<usize>, css: &str) {
+        // Relative urls in the stylesheet (e.g. in @font-face rules) are resolved against the document
+        let url_data = self.base_url.clone().unwrap_or_else(|| {
+            "data:text/css;charset=utf-8;base64,"
+                .parse::<Url>()
+                .unwrap()
+        });
         let data = Stylesheet::from_str(
             css,
-            UrlExtraData::from(
-                "data:text/css;charset=utf-8;base64,"
-                    .parse::<Url>()
-                    .unwrap(),
-            ),
+            UrlExtraData::from(url_data),
             Origin::UserAgent,
             ServoArc::new(self.guard.wrap(MediaList::empty())),
             self.guard.clone(),
@@ -300,13 +474,282 @@ impl Document {
             AllowImportRules::Yes,
         );
 
-        self.stylist
-            .append_stylesheet(DocumentStyleSheet(ServoArc::new(data)), &self.guard.read());
+        self.load_font_faces(&data);
+
+        // Cascade before the first sheet from an element later in the document
+        let sheet = DocumentStyleSheet(ServoArc::new(data));
+        let position = owner.and_then(|owner| {
+            let path = self.tree_path(owner);
+            self.stylesheets
+                .iter()
+                .position(|(other, _)| other.is_some_and(|other| self.tree_path(other) > path))
+        });
+        match position {
+            Some(idx) => {
+                let before = self.stylesheets[idx].1.clone();
+                self.stylist
+                    .insert_stylesheet_before(sheet.clone(), before, &self.guard.read());
+                self.stylesheets.insert(idx, (owner, sheet));
+            }
+            None => {
+                self.stylist
+                    .append_stylesheet(sheet.clone(), &self.guard.read());
+                self.stylesheets.push((owner, sheet));
+            }
+        }
 
         self.stylist
             .force_stylesheet_origins_dirty(Origin::Author.into());
     }
 
+    /// Remove the stylesheets from a `<style>` or `<link>` element that's being removed from the document
+    fn remove_stylesheets(&mut self, owner: usize) {
+        let mut removed = false;
+        let guard = self.guard.read();
+        self.stylesheets.retain(|(other, sheet)| {
+            if *other != Some(owner) {
+                return true;
+            }
+            self.stylist.remove_stylesheet(sheet.clone(), &guard);
+            removed = true;
+            false
+        });
+        drop(guard);
+
+        if removed {
+            self.stylist
+                .force_stylesheet_origins_dirty(Origin::Author.into());
+        }
+    }
+
+    /// The index of each of a node's ancestors (and the node itself) within its parent, starting from the root.
+    /// Comparing these orders nodes as they appear in the document.
+    fn tree_path(&self, node_id: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut current = self.nodes.get(node_id);
+        while let Some(node) = current {
+            let Some(parent) = node.parent.and_then(|id| self.nodes.get(id)) else {
+                break;
+            };
+            path.push(
+                parent
+                    .children
+                    .iter()
+                    .position(|id| *id == node.id)
+                    .unwrap_or(0),
+            );
+            current = Some(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Start fetching the fonts declared by the stylesheet's `@font-face` rules. They're registered with the
+    /// document's fonts as they arrive.
+    fn load_font_faces(&mut self, stylesheet: &Stylesheet) {
+        let mut font_faces = Vec::new();
+        let mut errors = Vec::new();
+        let guard = self.guard.read();
+        for rule in stylesheet.effective_rules(self.stylist.device(), &guard) {
+            let CssRule::FontFace(font_face) = rule else {
+                continue;
+            };
+            let font_face = font_face.read_with(&guard);
+            let (Some(face), Some(sources)) = (
+                FontFaceDescriptors::from_rule(font_face),
+                &font_face.sources,
+            ) else {
+                continue;
+            };
+
+            // TODO: local() sources
+            let mut urls = Vec::new();
+            for source in sources.0.iter() {
+                let Source::Url(url_source) = source else {
+                    continue;
+                };
+                match self.resolve_url(url_source.url.as_str()) {
+                    Ok(url) => urls.push(url),
+                    Err(error) => errors.push(error),
+                }
+            }
+            if !urls.is_empty() {
+                font_faces.push((face, urls));
+            }
+        }
+        drop(guard);
+
+        for (face, urls) in font_faces {
+            self.loader.fetch_font(face, urls);
+        }
+        for error in errors {
+            self.report_error(error);
+        }
+    }
+
+    /// Apply any linked resources that have finished loading. `cx` is woken when more arrive. Returns true if the
+    /// document changed and needs to be redrawn.
+    pub fn poll_resources(&mut self, cx: &mut Context) -> bool {
+        let mut changed = false;
+        while let Poll::Ready(resource) = self.loader.poll_next(cx) {
+            match resource {
+                Ok(resource) => {
+                    self.apply_resource(resource);
+                    changed = true;
+                }
+                Err(error) => self.report_error(error),
+            }
+        }
+        changed
+    }
+
+    /// Wait for every resource that is currently loading to arrive and be applied
+    pub async fn load_resources(&mut self) {
+        std::future::poll_fn(|cx| {
+            self.poll_resources(cx);
+            if self.loader.is_idle() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    fn apply_resource(&mut self, resource: Resource) {
+        match resource {
+            Resource::Image(node_id, image) => {
+                let Some(element_data) = self
+                    .nodes
+                    .get_mut(node_id)
+                    .and_then(|node| node.element_data_mut())
+                else {
+                    return;
+                };
+                element_data.image = Some(image);
+
+                // The image's natural size feeds into layout
+                self.invalidate_layout(node_id);
+            }
+            Resource::Stylesheet(node_id, css) => {
+                // The `<link>` was removed while its stylesheet was loading
+                if !self.nodes.contains(node_id) {
+                    return;
+                }
+                let css = html_escape::decode_html_entities(&css);
+                self.insert_stylesheet(Some(node_id), &css);
+            }
+            Resource::Font(face, url, font) => {
+                if self.fonts.register_web_font(&face, font) {
+                    // Text may be measured with a different font now, without any computed values changing
+                    self.invalidate_all_layout();
+                } else {
+                    self.report_error(ResourceError::InvalidFont(url));
+                }
+            }
+        }
+    }
+
+    /// Set the node under the pointer, which matches `:hover` along with its ancestors. Returns true if any styles
+    /// need to be recomputed.
+    pub fn set_hover_node(&mut self, node_id: Option<usize>) -> bool {
+        let old_id = std::mem::replace(&mut self.hover_node_id, node_id);
+        self.move_element_state(ElementState::HOVER, old_id, node_id, true)
+    }
+
+    /// Set the node the pointer is being pressed on, which matches `:active` along with its ancestors. Returns true if
+    /// any styles need to be recomputed.
+    pub fn set_active_node(&mut self, node_id: Option<usize>) -> bool {
+        let old_id = std::mem::replace(&mut self.active_node_id, node_id);
+        self.move_element_state(ElementState::ACTIVE, old_id, node_id, true)
+    }
+
+    /// Focus the closest focusable element to a node (e.g. the `<button>` around some clicked text), or remove focus
+    /// if there isn't one. Only the focused element matches `:focus`. Returns true if any styles need to be
+    /// recomputed.
+    pub fn set_focus_node(&mut self, node_id: Option<usize>) -> bool {
+        let mut focus_id = node_id;
+        while let Some(node) = focus_id.and_then(|id| self.nodes.get(id)) {
+            if node.is_focusable() {
+                break;
+            }
+            focus_id = node.parent;
+        }
+
+        let old_id = std::mem::replace(&mut self.focus_node_id, focus_id);
+        let focus_changed = self.move_element_state(ElementState::FOCUS, old_id, focus_id, false);
+
+        // Like browsers, only show a focus ring (`:focus-visible`) for a mouse click if the element takes text input
+        let has_ring = |doc: &Self, node_id: Option<usize>| {
+            node_id.filter(|id| doc.nodes.get(*id).is_some_and(Node::takes_text_input))
+        };
+        let (old_ring_id, ring_id) = (has_ring(self, old_id), has_ring(self, focus_id));
+        let ring_changed =
+            self.move_element_state(ElementState::FOCUSRING, old_ring_id, ring_id, false);
+
+        focus_changed || ring_changed
+    }
+
+    pub fn focus_node_id(&self) -> Option<usize> {
+        self.focus_node_id
+    }
+
+    /// Move a state from one node to another (and their ancestors if `with_ancestors`), marking the nodes that change
+    /// for restyle
+    fn move_element_state(
+        &mut self,
+        state: ElementState,
+        old_id: Option<usize>,
+        new_id: Option<usize>,
+        with_ancestors: bool,
+    ) -> bool {
+        if old_id == new_id {
+            return false;
+        }
+
+        let chain = |node_id: Option<usize>| {
+            let mut ids = Vec::new();
+            let mut next = node_id;
+            while let Some(node) = next.and_then(|id| self.nodes.get(id)) {
+                ids.push(node.id);
+                next = node.parent.filter(|_| with_ancestors);
+            }
+            ids
+        };
+        let old_chain = chain(old_id);
+        let new_chain = chain(new_id);
+
+        let mut changed = false;
+        for node_id in old_chain.iter().filter(|id| !new_chain.contains(id)) {
+            self.snapshot_node(*node_id);
+            self.nodes[*node_id].element_state.remove(state);
+            changed = true;
+        }
+        for node_id in new_chain.iter().filter(|id| !old_chain.contains(id)) {
+            self.snapshot_node(*node_id);
+            self.nodes[*node_id].element_state.insert(state);
+            changed = true;
+        }
+        changed
+    }
+
+    /// Clear the cached layout of a node and its ancestors, whose sizes may depend on it
+    pub fn invalidate_layout(&mut self, node_id: usize) {
+        let mut next = Some(node_id);
+        while let Some(node_id) = next {
+            let node = &mut self.nodes[node_id];
+            node.cache.clear();
+            next = node.layout_parent.or(node.parent);
+        }
+    }
+
+    /// Clear every node's cached layout, for changes that can affect any of them (e.g. a web font loading)
+    pub fn invalidate_all_layout(&mut self) {
+        for (_, node) in self.nodes.iter_mut() {
+            node.cache.clear();
+        }
+    }
+
     /// Restyle the tree and then relayout it
     pub fn resolve(&mut self) {
         if TDocument::as_node(&&self.nodes[0])
@@ -321,13 +764,15 @@ impl Document {
         self.resolve_stylist();
 
         // Merge stylo into taffy
+        self.fixed_nodes.clear();
+        self.sticky_nodes.clear();
         self.flush_styles_to_layout(vec![self.root_element().id], None, taffy::Display::Block);
 
         // Next we resolve layout with the data resolved by stlist
         self.resolve_layout();
     }
 
-    // Takes (x, y) co-ordinates (relative to the )
+    /// Find the deepest node at a point in the viewport
     pub fn hit(&self, x: f32, y: f32) -> Option<usize> {
         if TDocument::as_node(&&self.nodes[0])
             .first_element_child()
@@ -337,7 +782,74 @@ impl Document {
             return None;
         }
 
-        self.root_element().hit(x, y)
+        // Fixed elements are drawn over the page and don't scroll with it
+        let fixed_hit = self
+            .fixed_nodes
+            .iter()
+            .rev()
+            .find_map(|node_id| self.nodes.get(*node_id)?.hit(x, y));
+
+        fixed_hit.or_else(|| self.root_element().hit(x, y + self.viewport_scroll as f32))
+    }
+
+    /// How far the page has been scrolled down, in CSS pixels
+    pub fn viewport_scroll(&self) -> f64 {
+        self.viewport_scroll
+    }
+
+    /// Scroll the page down by `dy` CSS pixels (or up if it's negative), without scrolling past either end
+    pub fn scroll_viewport_by(&mut self, dy: f64) {
+        let content_height = self.root_element().final_layout.size.height as f64;
+        let viewport_height = self.stylist.device().au_viewport_size().height.to_f64_px();
+        self.viewport_scroll = (self.viewport_scroll + dy)
+            .min(content_height - viewport_height)
+            .max(0.0);
+
+        self.update_sticky_offsets();
+    }
+
+    /// Scroll the innermost scroll container containing `node_id` that can still move right by `dx` and down by `dy`
+    /// CSS pixels (or left and up if they're negative), one axis at a time. Once every container has reached its end
+    /// the page itself is scrolled, which only scrolls vertically.
+    pub fn scroll_node_by(&mut self, node_id: Option<usize>, dx: f64, dy: f64) {
+        let mut delta = Point {
+            x: dx as f32,
+            y: dy as f32,
+        };
+        let mut current = node_id;
+        while let Some(id) = current {
+            let node = &mut self.nodes[id];
+            if node.is_scroll_container() {
+                let range = node.scroll_range();
+                let offset = Point {
+                    x: (node.scroll_offset.x + delta.x).clamp(0.0, range.x),
+                    y: (node.scroll_offset.y + delta.y).clamp(0.0, range.y),
+                };
+                if offset.x != node.scroll_offset.x {
+                    delta.x = 0.0;
+                }
+                if offset.y != node.scroll_offset.y {
+                    delta.y = 0.0;
+                }
+                node.scroll_offset = offset;
+                if delta.x == 0.0 && delta.y == 0.0 {
+                    break;
+                }
+            }
+            current = node.parent;
+        }
+
+        if delta.y != 0.0 {
+            self.scroll_viewport_by(delta.y as f64);
+        } else {
+            // Sticky elements may be stuck to one of the containers that scrolled
+            self.update_sticky_offsets();
+        }
+    }
+
+    /// Elements with `position: fixed`, which are drawn after (and so on top of) the rest of the page
+    pub fn fixed_nodes(&self) -> &[usize] {
+        &self.fixed_nodes
     }
 
     /// Update the device and reset the stylist to process the new size
@@ -367,10 +879,29 @@ impl Document {
             height: AvailableSpace::Definite(size.height.to_f32_px()),
         };
 
-        let root_node_id = taffy::NodeId::from(self.root_element().id);
+        let root_id = self.root_element().id;
+        let root_node_id = taffy::NodeId::from(root_id);
+
+        // The root element's containing block is the viewport
+        self.resolve_calc_styles(
+            &[root_id],
+            available_space.into_options(),
+            taffy::FlexDirection::Row,
+        );
 
+        // Only nodes whose cached layout was invalidated (or that are given a different size) are laid out again
         taffy::compute_root_layout(self, root_node_id, available_space);
+        self.layout_fixed_nodes();
         taffy::round_layout(self, root_node_id);
+
+        // Content may have shrunk, leaving scroll containers scrolled past its end
+        for (_, node) in self.nodes.iter_mut() {
+            let range = node.scroll_range();
+            node.scroll_offset.x = node.scroll_offset.x.min(range.x);
+            node.scroll_offset.y = node.scroll_offset.y.min(range.y);
+        }
+
+        self.update_sticky_offsets();
     }
 
     pub fn set_document(&mut self, _content: String) {}
@@ -0,0 +1,38 @@
+//! Input events that the renderer has hit-tested against the DOM
+//!
+//! The renderer works out which node an event targets and hands it to the document (see
+//! [`DocumentLike::handle_event`](crate::DocumentLike::handle_event)), which is responsible for dispatching it to
+//! whatever listeners it knows about.
+
+/// An event targeted at a node in the document
+#[derive(Debug, Clone)]
+pub struct RendererEvent {
+    /// The id of the deepest node under the pointer
+    pub target: usize,
+    pub data: EventData,
+}
+
+impl RendererEvent {
+    /// The name of the event, in the form used by DOM event listeners (e.g. "click")
+    pub fn name(&self) -> &'static str {
+        self.data.name()
+    }
+}
+
+/// The kind of event and its associated data
+///
+/// Positions are in CSS pixels relative to the top-left corner of the viewport
+#[derive(Debug, Clone, Copy)]
+pub enum EventData {
+    Click { x: f32, y: f32 },
+    MouseMove { x: f32, y: f32 },
+}
+
+impl EventData {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventData::Click { .. } => "click",
+            EventData::MouseMove { .. } => "mousemove",
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use std::borrow::Cow;
 use std::collections::HashSet;
-use std::sync::Arc;
 
 use crate::node::{Attribute, ElementNodeData, Node, NodeData};
 use crate::Document;
@@ -93,13 +92,9 @@ impl<'a> DocumentHtmlParser<'a> {
         let href_attr = node.attr(local_name!("href"));
 
         if let (Some("stylesheet"), Some(href)) = (rel_attr, href_attr) {
-            let url = self.doc.resolve_url(&href);
-            match crate::util::fetch_string(url.as_str()) {
-                Ok(css) => {
-                    let css = html_escape::decode_html_entities(&css);
-                    self.doc.add_stylesheet(&css);
-                }
-                Err(_) => eprintln!("Error fetching stylesheet {}", url),
+            match self.doc.resolve_url(&href) {
+                Ok(url) => self.doc.loader.fetch_stylesheet(target_id, url),
+                Err(error) => self.doc.report_error(error),
             }
         }
     }
@@ -108,18 +103,9 @@ impl<'a> DocumentHtmlParser<'a> {
         let node = self.node(target_id);
         if let Some(raw_src) = node.attr(local_name!("src")) {
             if raw_src.len() > 0 {
-                let src = self.doc.resolve_url(&raw_src);
-
-                // FIXME: Image fetching should not be a synchronous network request during parsing
-                let image_result = crate::util::fetch_image(src.as_str());
-                match image_result {
-                    Ok(image) => {
-                        self.node_mut(target_id).element_data_mut().unwrap().image =
-                            Some(Arc::new(image));
-                    }
-                    Err(_) => {
-                        eprintln!("Error fetching image {}", src);
-                    }
+                match self.doc.resolve_url(&raw_src) {
+                    Ok(src) => self.doc.loader.fetch_image(target_id, src),
+                    Err(error) => self.doc.report_error(error),
                 }
             }
         }
@@ -402,3 +388,27 @@ fn parses_some_html() {
 
     // Now our tree should have some nodes in it
 }
+
+#[test]
+fn stylesheets_cascade_in_document_order() {
+    let mut doc = Document::from_html_for_test(
+        r#"<!DOCTYPE html><html><head>
+        <link rel="stylesheet" href="data:text/css,p%20%7B%20color%3A%20red%20%7D">
+        <style>p { color: blue }</style>
+        </head><body><p>hello world</p></body></html>"#,
+    );
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(doc.load_resources());
+
+    // The linked stylesheet arrives after the <style> element's, but it comes first in the document
+    let owners: Vec<_> = doc
+        .stylesheets
+        .iter()
+        .map(|(owner, _)| {
+            let element = doc.nodes[owner.unwrap()].element_data().unwrap();
+            element.name.local.to_string()
+        })
+        .collect();
+    assert_eq!(owners, ["link", "style"]);
+}
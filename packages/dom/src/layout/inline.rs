@@ -0,0 +1,1090 @@
+//! Inline formatting contexts
+//!
+//! Taffy only implements block, flex and grid layout, so block containers whose children are all inline-level are
+//! laid out here instead. Their text and inline descendants are flattened into a sequence of items which are broken
+//! into line boxes. Each descendant is then positioned from the fragments it ended up with: text nodes get the glyphs
+//! that fell inside them, and inline boxes like `<span>` get the bounding box of their contents plus their own padding
+//! and border.
+
+use std::collections::HashMap;
+
+use html5ever::local_name;
+use style::computed_values::direction::T as Direction;
+use style::values::computed::Length;
+use style::values::generics::box_::{VerticalAlign, VerticalAlignKeyword};
+use style::values::specified::TextAlignKeyword;
+use taffy::{
+    compute_leaf_layout, AvailableSpace, Layout, LayoutInput, LayoutOutput, LayoutPartialTree,
+    Line, MaybeMath, NodeId, Point, Rect, RequestedAxis, ResolveOrZero, RunMode, Size, SizingMode,
+};
+use unicode_bidi::{BidiInfo, Level};
+
+use crate::node::{DisplayOuter, Node, NodeData};
+use crate::text::{
+    bidi_levels, layout_preserved_space, layout_word, segment_text, FontContext, LineMetrics,
+    PositionedGlyph, TextLayout, TextLine, TextSegment, TextStyle, LINE_FIT_EPSILON,
+};
+use crate::Document;
+
+pub(crate) fn compute_inline_layout(
+    tree: &mut Document,
+    node_id: NodeId,
+    inputs: LayoutInput,
+) -> LayoutOutput {
+    let root_id = usize::from(node_id);
+    let root = &tree.nodes[root_id];
+    let style = root.style.clone();
+    let root_style = root.text_style(&tree.fonts);
+    // Every line is at least as tall as a line of the container's own text (the "strut")
+    let strut = root_style.line_metrics(&tree.fonts);
+    let align = text_align_factor(root);
+
+    let inset = style.padding.resolve_or_zero(inputs.parent_size.width)
+        + style.border.resolve_or_zero(inputs.parent_size.width);
+    let available_width = inputs
+        .known_dimensions
+        .width
+        .map(AvailableSpace::Definite)
+        .unwrap_or(inputs.available_space.width)
+        .maybe_sub(inset.left + inset.right);
+
+    let mut collector = ItemCollector::default();
+    collector.collect_children(tree, root_id, available_width, 0.0);
+    let items = collector.finish(root_style.rtl);
+
+    let output = compute_leaf_layout(inputs, &style, |known_dimensions, available_space| {
+        let max_width = known_dimensions.width.or(match available_space.width {
+            AvailableSpace::Definite(width) => Some(width),
+            AvailableSpace::MinContent => Some(0.0),
+            AvailableSpace::MaxContent => None,
+        });
+        let lines = break_lines(&items, strut, max_width);
+        Size {
+            width: known_dimensions
+                .width
+                .unwrap_or_else(|| lines.iter().map(|line| line.width).fold(0.0, f32::max)),
+            height: known_dimensions
+                .height
+                .unwrap_or_else(|| lines.iter().map(LineBox::height).sum()),
+        }
+    });
+
+    if inputs.run_mode != RunMode::PerformLayout {
+        return output;
+    }
+
+    let content_width = output.size.width - (inset.left + inset.right);
+    let mut lines = break_lines(&items, strut, Some(content_width));
+    for line in &mut lines {
+        line.reorder(&items);
+    }
+    let origin = Point {
+        x: inset.left,
+        y: inset.top,
+    };
+
+    // Position every fragment relative to the container, then convert that to each node's own coordinates
+    let mut bounds = HashMap::new();
+    let mut text_lines: HashMap<usize, Vec<(usize, TextLine)>> = HashMap::new();
+    let mut line_top = origin.y;
+    for (line_idx, line) in lines.iter().enumerate() {
+        let line_left = origin.x + align * (content_width - line.width).max(0.0);
+        let baseline = line_top + line.ascent;
+
+        for &(item_idx, x) in &line.items {
+            let item = &items[item_idx];
+            let left = line_left + x;
+            let baseline = baseline - item.align.shift();
+            let item_bounds = match &item.kind {
+                ItemKind::Text { glyphs, metrics } => {
+                    let fragments = text_lines.entry(item.node_id).or_default();
+                    match fragments.last_mut() {
+                        // Words from the same text node on the same line share a TextLine
+                        Some((idx, text_line)) if *idx == line_idx => {
+                            let offset = left - text_line.x;
+                            text_line
+                                .glyphs
+                                .extend(glyphs.iter().map(|glyph| PositionedGlyph {
+                                    x: glyph.x + offset,
+                                    ..*glyph
+                                }));
+                            text_line.width = offset + item.width;
+                        }
+                        _ => fragments.push((
+                            line_idx,
+                            TextLine {
+                                glyphs: glyphs.clone(),
+                                x: left,
+                                width: item.width,
+                                baseline,
+                            },
+                        )),
+                    }
+
+                    let top = baseline - metrics.baseline;
+                    Bounds {
+                        left,
+                        top,
+                        right: left + item.width,
+                        bottom: top + metrics.line_height,
+                    }
+                }
+                ItemKind::Atomic { size, margin } => {
+                    let left = left + margin.left;
+                    let top = match item.align {
+                        ItemAlign::Baseline(_) => baseline - size.height - margin.bottom,
+                        ItemAlign::LineTop => line_top + margin.top,
+                        ItemAlign::LineBottom => {
+                            line_top + line.height() - size.height - margin.bottom
+                        }
+                    };
+                    tree.compute_child_layout(
+                        NodeId::from(item.node_id),
+                        LayoutInput {
+                            run_mode: RunMode::PerformLayout,
+                            sizing_mode: SizingMode::InherentSize,
+                            axis: RequestedAxis::Both,
+                            known_dimensions: size.map(Some),
+                            parent_size: Size {
+                                width: Some(content_width),
+                                height: None,
+                            },
+                            available_space: size.map(AvailableSpace::Definite),
+                            vertical_margins_are_collapsible: Line::FALSE,
+                        },
+                    );
+                    Bounds {
+                        left,
+                        top,
+                        right: left + size.width,
+                        bottom: top + size.height,
+                    }
+                }
+                ItemKind::LineBreak => Bounds {
+                    left,
+                    top: line_top,
+                    right: left,
+                    bottom: line_top + line.height(),
+                },
+                // The edges of inline boxes only fix where their border boxes start and end horizontally. The
+                // height comes from their contents.
+                ItemKind::InlineStart { margin } => Bounds {
+                    left: left + margin,
+                    top: baseline,
+                    right: left + margin,
+                    bottom: baseline,
+                },
+                ItemKind::InlineEnd { margin } => Bounds {
+                    left: left + item.width - margin,
+                    top: baseline,
+                    right: left + item.width - margin,
+                    bottom: baseline,
+                },
+            };
+
+            bounds
+                .entry(item.node_id)
+                .and_modify(|existing: &mut Bounds| *existing = existing.union(item_bounds))
+                .or_insert(item_bounds);
+        }
+
+        line_top += line.height();
+    }
+
+    union_inline_box_bounds(tree, root_id, content_width, &mut bounds);
+    place_children(tree, root_id, Point::ZERO, &bounds, &mut text_lines);
+
+    LayoutOutput {
+        first_baselines: Point {
+            x: None,
+            y: lines.first().map(|line| origin.y + line.ascent),
+        },
+        ..output
+    }
+}
+
+/// How far along the free space in each line its contents should be shifted
+fn text_align_factor(node: &Node) -> f32 {
+    let Some(style) = node.primary_styles() else {
+        return 0.0;
+    };
+    // The start of right-to-left lines is on the right
+    let start = match style.get_inherited_box().clone_direction() {
+        Direction::Ltr => 0.0,
+        Direction::Rtl => 1.0,
+    };
+    match style.clone_text_align() {
+        TextAlignKeyword::Start => start,
+        TextAlignKeyword::End => 1.0 - start,
+        TextAlignKeyword::Left | TextAlignKeyword::ServoLeft => 0.0,
+        TextAlignKeyword::Center | TextAlignKeyword::ServoCenter => 0.5,
+        TextAlignKeyword::Right | TextAlignKeyword::ServoRight => 1.0,
+        // TODO: stretch the spaces between words
+        TextAlignKeyword::Justify => start,
+    }
+}
+
+struct InlineItem {
+    node_id: usize,
+    /// Width of the collapsed whitespace before this item, if there was any
+    space_before: Option<f32>,
+    /// Whether the line can be broken before this item without a forced break
+    break_before: bool,
+    width: f32,
+    kind: ItemKind,
+    align: ItemAlign,
+    /// The bidi levels of the item and of the whitespace before it, which decide whether they're reversed along with
+    /// their neighbours when the line is put into visual order
+    level: Level,
+    space_level: Level,
+}
+
+enum ItemKind {
+    /// A word, or the part of one that falls inside a single text node
+    Text {
+        glyphs: Vec<PositionedGlyph>,
+        metrics: LineMetrics,
+    },
+    /// A box that Taffy lays out and which is placed on a line as a whole, like an image or an inline-block
+    Atomic { size: Size<f32>, margin: Rect<f32> },
+    /// A `<br>`
+    LineBreak,
+    /// The start of an inline box like `<span>`, as wide as its margin, border and padding on that side
+    InlineStart { margin: f32 },
+    /// The end of an inline box
+    InlineEnd { margin: f32 },
+}
+
+/// Where an item sits vertically on its line, from `vertical-align`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ItemAlign {
+    /// Raised above the baseline of the line by this much (or lowered, if negative)
+    Baseline(f32),
+    /// Lined up with the top or bottom of the line box. Only atomic boxes can do this, other items fall back to the
+    /// baseline.
+    LineTop,
+    LineBottom,
+}
+
+impl ItemAlign {
+    fn shift(self) -> f32 {
+        match self {
+            ItemAlign::Baseline(shift) => shift,
+            ItemAlign::LineTop | ItemAlign::LineBottom => 0.0,
+        }
+    }
+}
+
+impl InlineItem {
+    fn is_atomic(&self) -> bool {
+        matches!(self.kind, ItemKind::Atomic { .. })
+    }
+
+    fn is_line_break(&self) -> bool {
+        matches!(self.kind, ItemKind::LineBreak)
+    }
+
+    /// How far the item extends above and below the baseline of the line
+    fn ascent_descent(&self) -> (f32, f32) {
+        let (ascent, descent) = match &self.kind {
+            ItemKind::Text { metrics, .. } => {
+                (metrics.baseline, metrics.line_height - metrics.baseline)
+            }
+            // The bottom margin edge of atomic boxes sits on the baseline
+            ItemKind::Atomic { size, margin } => (size.height + margin.top + margin.bottom, 0.0),
+            ItemKind::LineBreak | ItemKind::InlineStart { .. } | ItemKind::InlineEnd { .. } => {
+                (0.0, 0.0)
+            }
+        };
+        match self.align {
+            ItemAlign::Baseline(shift) => (ascent + shift, descent - shift),
+            // These are placed once the height of the rest of the line is known
+            ItemAlign::LineTop | ItemAlign::LineBottom => (0.0, 0.0),
+        }
+    }
+
+    /// The height of an item aligned to the top or bottom of the line
+    fn line_aligned_height(&self) -> f32 {
+        match (&self.kind, self.align) {
+            (ItemKind::Atomic { size, margin }, ItemAlign::LineTop | ItemAlign::LineBottom) => {
+                size.height + margin.top + margin.bottom
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ItemCollector {
+    items: Vec<InlineItem>,
+    /// The text of the whole paragraph (with a placeholder for each atomic box), which bidi levels are resolved from
+    text: String,
+    /// Where each item, and the whitespace before it, starts in `text`
+    text_indices: Vec<(usize, usize)>,
+    /// Whitespace that has been seen since the last item, along with where it is in `text`. Runs of whitespace
+    /// collapse into a single space, and are removed entirely at the start and end of lines.
+    pending_space: Option<(f32, usize)>,
+    /// Whether there has been whitespace that the line can wrap at since the last item
+    pending_break: bool,
+}
+
+impl ItemCollector {
+    /// `wrap` is whether the `white-space` of the text or box being pushed allows lines to wrap. Any text the item
+    /// stands for is added to `text` after it's pushed.
+    fn push(&mut self, node_id: usize, width: f32, kind: ItemKind, align: ItemAlign, wrap: bool) {
+        let at_line_start = self.items.last().map_or(true, InlineItem::is_line_break);
+        // The end edge of an inline box goes with the text before it
+        let text_index = match kind {
+            ItemKind::InlineEnd { .. } => self.text.len().saturating_sub(1),
+            _ => self.text.len(),
+        };
+        let (space_before, break_before) = match kind {
+            // Whitespace before the end of an inline box is kept for whatever follows it, so that the end edge isn't
+            // left at the start of the next line
+            ItemKind::InlineEnd { .. } => (None, false),
+            ItemKind::LineBreak => {
+                self.pending_space = None;
+                self.pending_break = false;
+                (None, false)
+            }
+            _ => {
+                // Lines can also wrap on either side of atomic boxes
+                let next_to_atomic = matches!(kind, ItemKind::Atomic { .. })
+                    || self.items.last().map_or(false, InlineItem::is_atomic);
+                let break_before =
+                    std::mem::take(&mut self.pending_break) || (wrap && next_to_atomic);
+                (
+                    self.pending_space.take().filter(|_| !at_line_start),
+                    break_before,
+                )
+            }
+        };
+        self.text_indices.push((
+            text_index,
+            space_before.map_or(text_index, |(_, index)| index),
+        ));
+        self.items.push(InlineItem {
+            node_id,
+            space_before: space_before.map(|(width, _)| width),
+            break_before,
+            width,
+            kind,
+            align,
+            level: Level::ltr(),
+            space_level: Level::ltr(),
+        });
+    }
+
+    /// Resolve the bidi level of every item now that the paragraph's text is known
+    fn finish(mut self, rtl: bool) -> Vec<InlineItem> {
+        let levels = bidi_levels(&self.text, rtl);
+        let base = if rtl { Level::rtl() } else { Level::ltr() };
+        let level_at = |index: usize| levels.get(index).copied().unwrap_or(base);
+        for (item, (index, space_index)) in self.items.iter_mut().zip(self.text_indices) {
+            item.level = level_at(index);
+            item.space_level = level_at(space_index);
+        }
+        self.items
+    }
+
+    /// `shift` is how far the parent's baseline is raised above the line's, by the `vertical-align` of inline boxes
+    fn collect_children(
+        &mut self,
+        tree: &mut Document,
+        parent_id: usize,
+        available_width: AvailableSpace,
+        shift: f32,
+    ) {
+        let parent_style = tree.nodes[parent_id].text_style(&tree.fonts);
+        let children = tree.nodes[parent_id].layout_children.clone();
+        for child_id in children {
+            let node = &tree.nodes[child_id];
+            match &node.raw_dom_data {
+                NodeData::Text(data) => {
+                    let style = node.text_style(&tree.fonts);
+                    self.collect_text(&tree.fonts, child_id, &data.content, &style, shift)
+                }
+                NodeData::Element(_) => {
+                    if is_hidden(node) {
+                        continue;
+                    }
+
+                    if node
+                        .raw_dom_data
+                        .is_element_with_tag_name(&local_name!("br"))
+                    {
+                        self.push(
+                            child_id,
+                            0.0,
+                            ItemKind::LineBreak,
+                            ItemAlign::Baseline(0.0),
+                            false,
+                        );
+                        self.text.push('\n');
+                    } else if node.is_inline_box() {
+                        self.collect_inline_box(
+                            tree,
+                            child_id,
+                            available_width,
+                            &parent_style,
+                            shift,
+                        );
+                    } else {
+                        self.collect_atomic(tree, child_id, available_width, &parent_style, shift);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Inline boxes are broken across lines along with their contents, between items for their start and end edges
+    fn collect_inline_box(
+        &mut self,
+        tree: &mut Document,
+        node_id: usize,
+        available_width: AvailableSpace,
+        parent_style: &TextStyle,
+        shift: f32,
+    ) {
+        let node = &tree.nodes[node_id];
+        let width = available_width.into_option();
+        let margin = node.style.margin.resolve_or_zero(width);
+        let edges =
+            node.style.padding.resolve_or_zero(width) + node.style.border.resolve_or_zero(width);
+
+        let metrics = node.text_style(&tree.fonts).line_metrics(&tree.fonts);
+        let (ascent, descent) = (metrics.baseline, metrics.line_height - metrics.baseline);
+        let align = vertical_align(node, parent_style, &tree.fonts, ascent, descent);
+        let align = ItemAlign::Baseline(shift + align.shift());
+        let wrap = parent_style.white_space.wraps();
+
+        self.push(
+            node_id,
+            margin.left + edges.left,
+            ItemKind::InlineStart {
+                margin: margin.left,
+            },
+            align,
+            wrap,
+        );
+        self.collect_children(tree, node_id, available_width, align.shift());
+        self.push(
+            node_id,
+            edges.right + margin.right,
+            ItemKind::InlineEnd {
+                margin: margin.right,
+            },
+            align,
+            wrap,
+        );
+    }
+
+    fn collect_text(
+        &mut self,
+        fonts: &FontContext,
+        node_id: usize,
+        content: &str,
+        style: &TextStyle,
+        shift: f32,
+    ) {
+        let metrics = style.line_metrics(fonts);
+        let space_width = style.space_width(fonts);
+        let wrap = style.white_space.wraps();
+        let align = ItemAlign::Baseline(shift);
+
+        // Each item's text is copied into the paragraph's text as it's pushed, so that it ends up at the item's index
+        let base = self.text.len();
+        let mut copied = 0;
+        for (offset, segment) in segment_text(content, style.white_space) {
+            let (glyphs, width) = match segment {
+                TextSegment::Word(word) => layout_word(fonts, word, style),
+                // Preserved whitespace is laid out like a word, so that it isn't removed at the start of lines
+                TextSegment::PreservedSpace(space) => layout_preserved_space(fonts, space, style),
+                TextSegment::CollapsibleSpace => {
+                    self.pending_space = Some((space_width, base + offset));
+                    self.pending_break |= wrap;
+                    continue;
+                }
+                TextSegment::WordBreak => {
+                    self.pending_break |= wrap;
+                    continue;
+                }
+                TextSegment::Newline => {
+                    self.text.push_str(&content[copied..offset]);
+                    copied = offset;
+                    self.push(node_id, 0.0, ItemKind::LineBreak, align, wrap);
+                    continue;
+                }
+            };
+            self.text.push_str(&content[copied..offset]);
+            copied = offset;
+
+            // Indentation stays on the line with the text that follows it
+            let at_line_start = self.items.last().map_or(true, InlineItem::is_line_break);
+            self.push(
+                node_id,
+                width,
+                ItemKind::Text { glyphs, metrics },
+                align,
+                wrap,
+            );
+            if let TextSegment::PreservedSpace(_) = segment {
+                self.pending_break |= wrap && !at_line_start;
+            }
+        }
+        self.text.push_str(&content[copied..]);
+    }
+
+    fn collect_atomic(
+        &mut self,
+        tree: &mut Document,
+        node_id: usize,
+        available_width: AvailableSpace,
+        parent_style: &TextStyle,
+        shift: f32,
+    ) {
+        let margin = tree.nodes[node_id]
+            .style
+            .margin
+            .resolve_or_zero(available_width.into_option());
+        let output = tree.compute_child_layout(
+            NodeId::from(node_id),
+            LayoutInput {
+                run_mode: RunMode::ComputeSize,
+                sizing_mode: SizingMode::InherentSize,
+                axis: RequestedAxis::Both,
+                known_dimensions: Size::NONE,
+                parent_size: Size {
+                    width: available_width.into_option(),
+                    height: None,
+                },
+                available_space: Size {
+                    width: available_width.maybe_sub(margin.left + margin.right),
+                    height: AvailableSpace::MaxContent,
+                },
+                vertical_margins_are_collapsible: Line::FALSE,
+            },
+        );
+
+        let height = output.size.height + margin.top + margin.bottom;
+        let align =
+            match vertical_align(&tree.nodes[node_id], parent_style, &tree.fonts, height, 0.0) {
+                ItemAlign::Baseline(own_shift) => ItemAlign::Baseline(shift + own_shift),
+                align => align,
+            };
+
+        self.push(
+            node_id,
+            output.size.width + margin.left + margin.right,
+            ItemKind::Atomic {
+                size: output.size,
+                margin,
+            },
+            align,
+            parent_style.white_space.wraps(),
+        );
+        // Atomic boxes are neutral, and take the direction of the text around them
+        self.text.push('\u{FFFC}');
+    }
+}
+
+/// Where an inline-level box sits relative to its parent's baseline, from its `vertical-align`. `ascent` and `descent`
+/// are how far the box extends above and below its own baseline.
+fn vertical_align(
+    node: &Node,
+    parent_style: &TextStyle,
+    fonts: &FontContext,
+    ascent: f32,
+    descent: f32,
+) -> ItemAlign {
+    let Some(style) = node.primary_styles() else {
+        return ItemAlign::Baseline(0.0);
+    };
+    let font_size = parent_style.font_size;
+    let parent = parent_style.line_metrics(fonts);
+    let parent_descent = parent.line_height - parent.baseline;
+
+    match style.clone_vertical_align() {
+        VerticalAlign::Keyword(keyword) => match keyword {
+            VerticalAlignKeyword::Baseline => ItemAlign::Baseline(0.0),
+            VerticalAlignKeyword::Sub => ItemAlign::Baseline(-font_size / 5.0),
+            VerticalAlignKeyword::Super => ItemAlign::Baseline(font_size / 3.0),
+            // The middle of the box lines up with the middle of the parent's lowercase letters, which we take to be a
+            // quarter of an em above the baseline
+            VerticalAlignKeyword::Middle => {
+                ItemAlign::Baseline(font_size / 4.0 - (ascent - descent) / 2.0)
+            }
+            VerticalAlignKeyword::TextTop => ItemAlign::Baseline(parent.baseline - ascent),
+            VerticalAlignKeyword::TextBottom => ItemAlign::Baseline(descent - parent_descent),
+            VerticalAlignKeyword::Top => ItemAlign::LineTop,
+            VerticalAlignKeyword::Bottom => ItemAlign::LineBottom,
+        },
+        // Percentages are relative to the element's own line height
+        VerticalAlign::Length(length) => {
+            let line_height = node.text_style(fonts).line_metrics(fonts).line_height;
+            ItemAlign::Baseline(length.resolve(Length::new(line_height)).px())
+        }
+    }
+}
+
+fn is_hidden(node: &Node) -> bool {
+    node.style.display == taffy::Display::None || node.display_outer == DisplayOuter::None
+}
+
+struct LineBox {
+    /// Indexes of the items on the line, along with their offset from the start of the line
+    items: Vec<(usize, f32)>,
+    width: f32,
+    ascent: f32,
+    descent: f32,
+    /// The tallest item aligned to the top or bottom of the line
+    line_aligned_height: f32,
+}
+
+impl LineBox {
+    fn new(strut: LineMetrics) -> Self {
+        Self {
+            items: Vec::new(),
+            width: 0.0,
+            ascent: strut.baseline,
+            descent: strut.line_height - strut.baseline,
+            line_aligned_height: 0.0,
+        }
+    }
+
+    fn height(&self) -> f32 {
+        (self.ascent + self.descent).max(self.line_aligned_height)
+    }
+
+    /// Put the items into visual order, so that runs of right-to-left text read from the right. The whitespace before
+    /// each item is reordered along with the items, at its own bidi level.
+    fn reorder(&mut self, items: &[InlineItem]) {
+        let mut pieces = Vec::new();
+        let mut end = 0.0;
+        for &(item_idx, x) in &self.items {
+            let item = &items[item_idx];
+            if x > end {
+                pieces.push((None, x - end, item.space_level));
+            }
+            pieces.push((Some(item_idx), item.width, item.level));
+            end = x + item.width;
+        }
+
+        let levels: Vec<Level> = pieces.iter().map(|(_, _, level)| *level).collect();
+        self.items.clear();
+        let mut x = 0.0;
+        for idx in BidiInfo::reorder_visual(&levels) {
+            let (item_idx, width, _) = pieces[idx];
+            if let Some(item_idx) = item_idx {
+                self.items.push((item_idx, x));
+            }
+            x += width;
+        }
+    }
+
+    /// Add an item to the end of the line, after `space` of collapsed whitespace
+    fn place(&mut self, item_idx: usize, item: &InlineItem, space: f32) {
+        self.width += space;
+        self.items.push((item_idx, self.width));
+        self.width += item.width;
+
+        let (ascent, descent) = item.ascent_descent();
+        self.ascent = self.ascent.max(ascent);
+        self.descent = self.descent.max(descent);
+        self.line_aligned_height = self.line_aligned_height.max(item.line_aligned_height());
+    }
+}
+
+/// Lines can be broken at forced breaks, and where `white-space` allows wrapping at whitespace, inside words where
+/// UAX #14 allows and on either side of atomic boxes
+fn is_break_opportunity(item: &InlineItem) -> bool {
+    item.break_before || item.is_line_break()
+}
+
+fn break_lines(items: &[InlineItem], strut: LineMetrics, max_width: Option<f32>) -> Vec<LineBox> {
+    let mut lines = Vec::new();
+    let mut line = LineBox::new(strut);
+
+    let mut start = 0;
+    while start < items.len() {
+        if items[start].is_line_break() {
+            line.place(start, &items[start], 0.0);
+            lines.push(std::mem::replace(&mut line, LineBox::new(strut)));
+            start += 1;
+            continue;
+        }
+
+        // Items that aren't separated by a break opportunity have to stay on the same line
+        let mut end = start + 1;
+        while end < items.len() && !is_break_opportunity(&items[end]) {
+            end += 1;
+        }
+        // Whitespace inside the group (where `white-space` doesn't allow wrapping at it) stays with the group
+        let space_within = |item: &InlineItem| item.space_before.unwrap_or(0.0);
+        let group_width: f32 = items[start].width
+            + items[start + 1..end]
+                .iter()
+                .map(|item| space_within(item) + item.width)
+                .sum::<f32>();
+
+        let mut space = 0.0;
+        if !line.items.is_empty() {
+            space = items[start].space_before.unwrap_or(0.0);
+            let fits = max_width.map_or(true, |max_width| {
+                line.width + space + group_width <= max_width + LINE_FIT_EPSILON
+            });
+            if !fits {
+                lines.push(std::mem::replace(&mut line, LineBox::new(strut)));
+                space = 0.0;
+            }
+        }
+
+        line.place(start, &items[start], space);
+        for (item_idx, item) in items.iter().enumerate().take(end).skip(start + 1) {
+            line.place(item_idx, item, space_within(item));
+        }
+        start = end;
+    }
+
+    if !line.items.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+    left: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+}
+
+impl Bounds {
+    fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+}
+
+/// Inline boxes take the bounds of all of their fragments, which may span several lines, between their start and end
+/// edges. Their padding and border extend above and below their contents without affecting the height of the line.
+fn union_inline_box_bounds(
+    tree: &Document,
+    node_id: usize,
+    content_width: f32,
+    bounds: &mut HashMap<usize, Bounds>,
+) -> Option<Bounds> {
+    let mut union: Option<Bounds> = None;
+    for &child_id in &tree.nodes[node_id].layout_children {
+        let child = &tree.nodes[child_id];
+        let child_bounds = if child.is_element() && !is_hidden(child) && child.is_inline_box() {
+            let contents = union_inline_box_bounds(tree, child_id, content_width, bounds);
+            let child_bounds = match (bounds.get(&child_id).copied(), contents) {
+                (Some(edges), Some(contents)) => Some(edges.union(contents)),
+                (edges, contents) => edges.or(contents),
+            };
+            let edges = child.style.padding.resolve_or_zero(Some(content_width))
+                + child.style.border.resolve_or_zero(Some(content_width));
+            let child_bounds = child_bounds.map(|child_bounds| Bounds {
+                top: child_bounds.top - edges.top,
+                bottom: child_bounds.bottom + edges.bottom,
+                ..child_bounds
+            });
+            if let Some(child_bounds) = child_bounds {
+                bounds.insert(child_id, child_bounds);
+            }
+            child_bounds
+        } else {
+            bounds.get(&child_id).copied()
+        };
+
+        if let Some(child_bounds) = child_bounds {
+            union = Some(union.map_or(child_bounds, |union| union.union(child_bounds)));
+        }
+    }
+    union
+}
+
+/// Give every descendant of an inline formatting context a layout relative to its parent. Nodes without any
+/// fragments (collapsed whitespace, empty or hidden elements) get an empty box at their parent's origin.
+fn place_children(
+    tree: &mut Document,
+    parent_id: usize,
+    parent_origin: Point<f32>,
+    bounds: &HashMap<usize, Bounds>,
+    text_lines: &mut HashMap<usize, Vec<(usize, TextLine)>>,
+) {
+    let children = tree.nodes[parent_id].layout_children.clone();
+    for (order, child_id) in children.into_iter().enumerate() {
+        let child_bounds = bounds.get(&child_id).copied().unwrap_or(Bounds {
+            left: parent_origin.x,
+            top: parent_origin.y,
+            right: parent_origin.x,
+            bottom: parent_origin.y,
+        });
+        let origin = Point {
+            x: child_bounds.left,
+            y: child_bounds.top,
+        };
+        let size = Size {
+            width: child_bounds.right - child_bounds.left,
+            height: child_bounds.bottom - child_bounds.top,
+        };
+
+        tree.set_unrounded_layout(
+            NodeId::from(child_id),
+            &Layout {
+                order: order as u32,
+                location: Point {
+                    x: origin.x - parent_origin.x,
+                    y: origin.y - parent_origin.y,
+                },
+                size,
+                ..Layout::new()
+            },
+        );
+
+        let child = &mut tree.nodes[child_id];
+        match child.raw_dom_data {
+            NodeData::Text(_) => {
+                let lines = text_lines
+                    .remove(&child_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(_, line)| TextLine {
+                        x: line.x - origin.x,
+                        baseline: line.baseline - origin.y,
+                        ..line
+                    })
+                    .collect();
+                child.text_layout = Some(TextLayout {
+                    lines,
+                    width: size.width,
+                    height: size.height,
+                });
+            }
+            NodeData::Element(_) if !is_hidden(child) && child.is_inline_box() => {
+                place_children(tree, child_id, origin, bounds, text_lines);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::WhiteSpace;
+
+    fn item(width: f32, space_before: bool, kind: ItemKind) -> InlineItem {
+        InlineItem {
+            node_id: 0,
+            space_before: space_before.then_some(5.0),
+            break_before: space_before,
+            width,
+            kind,
+            align: ItemAlign::Baseline(0.0),
+            level: Level::ltr(),
+            space_level: Level::ltr(),
+        }
+    }
+
+    fn word(width: f32, space_before: bool) -> InlineItem {
+        let metrics = LineMetrics {
+            line_height: 20.0,
+            baseline: 15.0,
+        };
+        let glyphs = Vec::new();
+        item(width, space_before, ItemKind::Text { glyphs, metrics })
+    }
+
+    #[test]
+    fn inline_box_edges_stay_with_their_contents() {
+        let strut = LineMetrics {
+            line_height: 20.0,
+            baseline: 15.0,
+        };
+        // "aaaa <span>bbbb</span>" where the span has 10px of padding on each side
+        let items = [
+            word(40.0, false),
+            item(10.0, true, ItemKind::InlineStart { margin: 0.0 }),
+            word(40.0, false),
+            item(10.0, false, ItemKind::InlineEnd { margin: 0.0 }),
+        ];
+
+        let lines = break_lines(&items, strut, None);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].width, 40.0 + 5.0 + 10.0 + 40.0 + 10.0);
+
+        // The whole span moves to the next line, edges included
+        let lines = break_lines(&items, strut, Some(80.0));
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].items.len(), 3);
+        assert_eq!(lines[1].width, 60.0);
+    }
+
+    #[test]
+    fn line_aligned_boxes_grow_the_line() {
+        let strut = LineMetrics {
+            line_height: 20.0,
+            baseline: 15.0,
+        };
+        let mut image = item(
+            30.0,
+            false,
+            ItemKind::Atomic {
+                size: Size {
+                    width: 30.0,
+                    height: 50.0,
+                },
+                margin: Rect {
+                    left: 0.0,
+                    right: 0.0,
+                    top: 0.0,
+                    bottom: 0.0,
+                },
+            },
+        );
+        image.align = ItemAlign::LineTop;
+
+        let lines = break_lines(&[word(40.0, false), image], strut, None);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].height(), 50.0);
+    }
+
+    /// Lay out a single text node with the given `white-space`, returning the width of each line
+    fn text_lines(text: &str, white_space: WhiteSpace, max_width: Option<f32>) -> Vec<f32> {
+        let fonts = FontContext::default();
+        let style = TextStyle {
+            white_space,
+            ..TextStyle::default()
+        };
+        let mut collector = ItemCollector::default();
+        collector.collect_text(&fonts, 0, text, &style, 0.0);
+        break_lines(
+            &collector.finish(false),
+            style.line_metrics(&fonts),
+            max_width,
+        )
+        .iter()
+        .map(|line| line.width)
+        .collect()
+    }
+
+    fn text_width(text: &str) -> f32 {
+        layout_word(&FontContext::default(), text, &TextStyle::default()).1
+    }
+
+    fn assert_widths(lines: Vec<f32>, expected: &[f32]) {
+        assert_eq!(lines.len(), expected.len(), "{lines:?} != {expected:?}");
+        for (line, expected) in lines.iter().zip(expected) {
+            assert!((line - expected).abs() < 0.01, "{lines:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn normal_collapses_whitespace_and_wraps() {
+        let text = "  one \n  two  ";
+        assert_widths(
+            text_lines(text, WhiteSpace::Normal, None),
+            &[text_width("one two")],
+        );
+        assert_widths(
+            text_lines(text, WhiteSpace::Normal, Some(0.0)),
+            &[text_width("one"), text_width("two")],
+        );
+    }
+
+    #[test]
+    fn nowrap_never_wraps() {
+        let text = "one \n two";
+        assert_widths(
+            text_lines(text, WhiteSpace::Nowrap, Some(0.0)),
+            &[text_width("one two")],
+        );
+    }
+
+    #[test]
+    fn pre_keeps_whitespace_and_newlines() {
+        let text = "one  two\n\n    three";
+        let expected = [text_width("one  two"), 0.0, text_width("    three")];
+        assert_widths(text_lines(text, WhiteSpace::Pre, None), &expected);
+        // Only newlines break the lines
+        assert_widths(text_lines(text, WhiteSpace::Pre, Some(0.0)), &expected);
+    }
+
+    #[test]
+    fn pre_wrap_keeps_whitespace_and_wraps() {
+        let text = "one  two\n  three";
+        assert_widths(
+            text_lines(text, WhiteSpace::PreWrap, None),
+            &[text_width("one  two"), text_width("  three")],
+        );
+        // Preserved spaces stay at the end of the line they follow
+        assert_widths(
+            text_lines(text, WhiteSpace::PreWrap, Some(0.0)),
+            &[
+                text_width("one  "),
+                text_width("two"),
+                text_width("  three"),
+            ],
+        );
+    }
+
+    #[test]
+    fn pre_line_collapses_spaces_but_keeps_newlines() {
+        let text = "one   two \n  three";
+        assert_widths(
+            text_lines(text, WhiteSpace::PreLine, None),
+            &[text_width("one two"), text_width("three")],
+        );
+        assert_widths(
+            text_lines(text, WhiteSpace::PreLine, Some(0.0)),
+            &[text_width("one"), text_width("two"), text_width("three")],
+        );
+    }
+
+    #[test]
+    fn breaks_inside_words_where_unicode_allows() {
+        assert_eq!(text_lines("日本語", WhiteSpace::Normal, Some(0.0)).len(), 3);
+        assert_eq!(text_lines("日本語", WhiteSpace::Nowrap, Some(0.0)).len(), 1);
+    }
+
+    #[test]
+    fn right_to_left_runs_are_reversed_on_their_line() {
+        let fonts = FontContext::default();
+        let style = TextStyle::default();
+        let mut collector = ItemCollector::default();
+        collector.collect_text(&fonts, 0, "abc אב גד", &style, 0.0);
+        let items = collector.finish(false);
+
+        let mut lines = break_lines(&items, style.line_metrics(&fonts), None);
+        lines[0].reorder(&items);
+        let order: Vec<usize> = lines[0]
+            .items
+            .iter()
+            .map(|(item_idx, _)| *item_idx)
+            .collect();
+        assert_eq!(order, [0, 2, 1]);
+
+        // The space between the Hebrew words stays between them
+        let space = style.space_width(&fonts);
+        let x = |item_idx: usize| {
+            lines[0]
+                .items
+                .iter()
+                .find(|(idx, _)| *idx == item_idx)
+                .unwrap()
+                .1
+        };
+        assert!((x(2) - (items[0].width + space)).abs() < 0.01);
+        assert!((x(1) - (x(2) + items[2].width + space)).abs() < 0.01);
+    }
+}
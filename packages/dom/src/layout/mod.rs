@@ -4,18 +4,23 @@
 //! However, in Blitz, we do a style pass then a layout pass.
 //! This is slower, yes, but happens fast enough that it's not a huge issue.
 
+mod inline;
+mod positioned;
+
 use crate::node::NodeData;
+use crate::stylo_to_taffy;
 use crate::{
     document::Document,
     image::{image_measure_function, ImageContext},
     node::Node,
-    text::{text_measure_function, FontMetrics, TextContext, WritingMode},
+    text::{layout_text, text_measure_function, FontContext, TextContext, TextStyle, WritingMode},
 };
 use html5ever::local_name;
 use taffy::{
     compute_block_layout, compute_cached_layout, compute_flexbox_layout, compute_grid_layout,
-    compute_leaf_layout, prelude::*, Cache, Dimension, FlexDirection, LayoutPartialTree, NodeId,
-    RoundTree, Size, Style, TraversePartialTree, TraverseTree,
+    compute_leaf_layout, prelude::*, Cache, Dimension, FlexDirection, LayoutPartialTree,
+    MaybeResolve, NodeId, ResolveOrZero, RoundTree, RunMode, Size, Style, TraversePartialTree,
+    TraverseTree,
 };
 
 impl Document {
@@ -25,21 +30,84 @@ impl Document {
     fn node_from_id_mut(&mut self, node_id: taffy::prelude::NodeId) -> &mut Node {
         &mut self.nodes[node_id.into()]
     }
+
+    /// Resolve the calc() values of nodes that share a containing block of the given content size and flex direction
+    pub(crate) fn resolve_calc_styles(
+        &mut self,
+        node_ids: &[usize],
+        basis: Size<Option<f32>>,
+        flex_direction: FlexDirection,
+    ) {
+        for &node_id in node_ids {
+            let node = &self.nodes[node_id];
+            if !node.has_calc {
+                continue;
+            }
+            let Some(computed) = node.primary_styles() else {
+                continue;
+            };
+            let mut style = node.style.clone();
+            stylo_to_taffy::resolve_calc(&computed, &mut style, basis, flex_direction);
+            drop(computed);
+
+            // Taffy doesn't know the style changed, so the node's cached layout may be stale
+            let node = &mut self.nodes[node_id];
+            if node.style != style {
+                node.style = style;
+                node.cache.clear();
+            }
+        }
+    }
+
+    /// Resolve the calc() values of a node's gaps and grid tracks, and of its children, against its content box before
+    /// its layout algorithm reads them
+    fn resolve_child_calc_styles(&mut self, node_id: NodeId, inputs: &taffy::LayoutInput) {
+        let node = self.node_from_id(node_id);
+        let children = node.layout_children.clone();
+        if !node.has_calc && !children.iter().any(|id| self.nodes[*id].has_calc) {
+            return;
+        }
+
+        let style = &node.style;
+        let border_box = inputs
+            .known_dimensions
+            .or(style.size.maybe_resolve(inputs.parent_size));
+        let insets = (style.padding.resolve_or_zero(inputs.parent_size.width)
+            + style.border.resolve_or_zero(inputs.parent_size.width))
+        .sum_axes();
+        let content_box = Size {
+            width: border_box.width.map(|w| (w - insets.width).max(0.0)),
+            height: border_box.height.map(|h| (h - insets.height).max(0.0)),
+        };
+
+        // The node's own cached layouts stay valid, as its content box only depends on the inputs they're cached by
+        if node.has_calc {
+            if let Some(computed) = node.primary_styles() {
+                let mut style = node.style.clone();
+                stylo_to_taffy::resolve_container_calc(&computed, &mut style, content_box);
+                drop(computed);
+                self.node_from_id_mut(node_id).style = style;
+            }
+        }
+
+        let flex_direction = self.node_from_id(node_id).style.flex_direction;
+        self.resolve_calc_styles(&children, content_box, flex_direction);
+    }
 }
 
 impl TraversePartialTree for Document {
     type ChildIter<'a> = ChildIter<'a>;
 
     fn child_ids(&self, node_id: NodeId) -> Self::ChildIter<'_> {
-        ChildIter(self.node_from_id(node_id).children.iter())
+        ChildIter(self.node_from_id(node_id).layout_children.iter())
     }
 
     fn child_count(&self, node_id: NodeId) -> usize {
-        self.node_from_id(node_id).children.len()
+        self.node_from_id(node_id).layout_children.len()
     }
 
     fn get_child_id(&self, node_id: NodeId, index: usize) -> NodeId {
-        NodeId::from(self.node_from_id(node_id).children[index])
+        NodeId::from(self.node_from_id(node_id).layout_children[index])
     }
 }
 impl TraverseTree for Document {}
@@ -50,7 +118,9 @@ impl LayoutPartialTree for Document {
     }
 
     fn set_unrounded_layout(&mut self, node_id: NodeId, layout: &Layout) {
-        self.node_from_id_mut(node_id).unrounded_layout = *layout;
+        let node = self.node_from_id_mut(node_id);
+        node.unrounded_layout = *layout;
+        node.static_position = layout.location;
     }
 
     fn get_cache_mut(&mut self, node_id: NodeId) -> &mut Cache {
@@ -63,15 +133,29 @@ impl LayoutPartialTree for Document {
         inputs: taffy::tree::LayoutInput,
     ) -> taffy::tree::LayoutOutput {
         compute_cached_layout(self, node_id, inputs, |tree, node_id, inputs| {
-            let node = tree.node_from_id_mut(node_id);
+            let node = tree.node_from_id(node_id);
+            if let NodeData::Text(data) = &node.raw_dom_data {
+                let text_style = node.text_style(&tree.fonts);
+                let output = lay_text(inputs, &tree.fonts, &node.style, &data.content, &text_style);
 
-            let font_metrics = FontMetrics {
-                char_width: 8.0,
-                char_height: 16.0,
-            };
+                // Keep the glyph positions around for the renderer
+                if inputs.run_mode == RunMode::PerformLayout {
+                    let text_layout = layout_text(
+                        &tree.fonts,
+                        &data.content,
+                        &text_style,
+                        Some(output.size.width),
+                    );
+                    tree.node_from_id_mut(node_id).text_layout = Some(text_layout);
+                }
+
+                return output;
+            }
 
+            tree.resolve_child_calc_styles(node_id, &inputs);
+
+            let node = tree.node_from_id_mut(node_id);
             match &node.raw_dom_data {
-                NodeData::Text(data) => lay_text(inputs, &node.style, &data.content, &font_metrics),
                 NodeData::Element(element_data) => {
                     // Hide hidden nodes
                     if let Some(value) = node.attr(local_name!("hidden")) {
@@ -115,13 +199,21 @@ impl LayoutPartialTree for Document {
                             inputs,
                             &node.style,
                             |known_dimensions, _available_space| {
-                                image_measure_function(known_dimensions, inputs.parent_size, &image_data, &node.style)
+                                image_measure_function(
+                                    known_dimensions,
+                                    inputs.parent_size,
+                                    &image_data,
+                                    &node.style,
+                                )
                             },
                         );
                     }
 
                     // The default CSS file will set
                     match node.style.display {
+                        Display::Block if node.is_inline_root => {
+                            inline::compute_inline_layout(tree, node_id, inputs)
+                        }
                         Display::Block => compute_block_layout(tree, node_id, inputs),
                         Display::Flex => compute_flexbox_layout(tree, node_id, inputs),
                         Display::Grid => compute_grid_layout(tree, node_id, inputs),
@@ -138,16 +230,23 @@ impl LayoutPartialTree for Document {
 
 fn lay_text(
     inputs: taffy::LayoutInput,
+    fonts: &FontContext,
     node: &Style,
     contents: &str,
-    font_metrics: &FontMetrics,
+    text_style: &TextStyle,
 ) -> taffy::LayoutOutput {
     compute_leaf_layout(inputs, &node, |known_dimensions, available_space| {
         let context = TextContext {
-            text_content: contents.trim(),
+            text_content: contents,
             writing_mode: WritingMode::Horizontal,
         };
-        text_measure_function(known_dimensions, available_space, &context, font_metrics)
+        text_measure_function(
+            known_dimensions,
+            available_space,
+            &context,
+            text_style,
+            fonts,
+        )
     })
 }
 
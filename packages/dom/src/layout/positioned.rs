@@ -0,0 +1,284 @@
+//! Elements positioned against the viewport rather than their parent
+//!
+//! Taffy has no notion of the viewport, so `position: fixed` elements are laid out as absolutely positioned children
+//! of their parent and then laid out again here against the viewport. `position: sticky` elements keep their place in
+//! the layout and are shifted by [`Node::sticky_offset`](crate::node::Node::sticky_offset) as the page or their scroll
+//! container scrolls.
+
+use crate::{document::Document, node::Node, stylo_to_taffy};
+use style::values::specified::box_::DisplayOutside;
+use taffy::{
+    prelude::*, LayoutInput, LayoutPartialTree, MaybeResolve, Point, RequestedAxis, RunMode,
+    SizingMode,
+};
+
+impl Document {
+    fn viewport_size(&self) -> Size<f32> {
+        let size = self.stylist.device().au_viewport_size();
+        Size {
+            width: size.width.to_f32_px(),
+            height: size.height.to_f32_px(),
+        }
+    }
+
+    /// Lay out each `position: fixed` element against the viewport, replacing the layout it was given inside its parent
+    pub(crate) fn layout_fixed_nodes(&mut self) {
+        let viewport = self.viewport_size();
+        let parent_size = viewport.map(Some);
+
+        for node_id in self.fixed_nodes.clone() {
+            if !self.nodes.contains(node_id) {
+                continue;
+            }
+            // Percentages in calc() were resolved against the parent when it was laid out
+            self.resolve_calc_styles(&[node_id], parent_size, FlexDirection::Row);
+            let style = self.nodes[node_id].style.clone();
+            let inset = Rect {
+                left: style.inset.left.resolve_to_option(viewport.width),
+                right: style.inset.right.resolve_to_option(viewport.width),
+                top: style.inset.top.resolve_to_option(viewport.height),
+                bottom: style.inset.bottom.resolve_to_option(viewport.height),
+            };
+            // Auto margins are treated as zero
+            let margin = style
+                .margin
+                .map(|margin| margin.resolve_to_option(viewport.width).unwrap_or(0.0));
+
+            // An auto size with both insets set stretches between them
+            let size = style.size.maybe_resolve(parent_size);
+            let known_dimensions = Size {
+                width: size.width.or_else(|| {
+                    let (left, right) = (inset.left?, inset.right?);
+                    Some((viewport.width - left - right - margin.left - margin.right).max(0.0))
+                }),
+                height: size.height.or_else(|| {
+                    let (top, bottom) = (inset.top?, inset.bottom?);
+                    Some((viewport.height - top - bottom - margin.top - margin.bottom).max(0.0))
+                }),
+            };
+
+            let output = self.compute_child_layout(
+                NodeId::from(node_id),
+                LayoutInput {
+                    run_mode: RunMode::PerformLayout,
+                    sizing_mode: SizingMode::InherentSize,
+                    axis: RequestedAxis::Both,
+                    known_dimensions,
+                    parent_size,
+                    available_space: viewport.map(AvailableSpace::Definite),
+                    vertical_margins_are_collapsible: Line::FALSE,
+                },
+            );
+            let size = output.size;
+
+            // Without insets the element stays where it would have been on the (unscrolled) page. Its own layout was
+            // replaced last time around, and isn't laid out again by its parent if the parent's layout is cached.
+            let node = &self.nodes[node_id];
+            let parent_position = node
+                .layout_parent
+                .or(node.parent)
+                .map_or(Point::ZERO, |parent_id| {
+                    self.page_position(parent_id, |node| &node.unrounded_layout)
+                });
+            let static_position = Point {
+                x: parent_position.x + node.static_position.x,
+                y: parent_position.y + node.static_position.y,
+            };
+            let location = Point {
+                x: match (inset.left, inset.right) {
+                    (Some(left), _) => left + margin.left,
+                    (None, Some(right)) => viewport.width - right - margin.right - size.width,
+                    (None, None) => static_position.x,
+                },
+                y: match (inset.top, inset.bottom) {
+                    (Some(top), _) => top + margin.top,
+                    (None, Some(bottom)) => viewport.height - bottom - margin.bottom - size.height,
+                    (None, None) => static_position.y,
+                },
+            };
+
+            let node = &mut self.nodes[node_id];
+            node.unrounded_layout = Layout {
+                location,
+                size,
+                ..node.unrounded_layout
+            };
+        }
+    }
+
+    /// The position of a node relative to the top-left of the page, from either its rounded or unrounded layout
+    fn page_position(&self, node_id: usize, layout: fn(&Node) -> &Layout) -> Point<f32> {
+        let mut position = Point::ZERO;
+        let mut current = Some(node_id);
+        while let Some(id) = current {
+            let node = &self.nodes[id];
+            position.x += layout(node).location.x;
+            position.y += layout(node).location.y;
+            current = node.layout_parent.or(node.parent);
+        }
+        position
+    }
+
+    /// Shift each `position: sticky` element so that it stays within its top/bottom insets of its nearest scroll
+    /// container (or the viewport) for as long as its containing block is in view
+    ///
+    /// TODO: horizontal stickiness
+    pub(crate) fn update_sticky_offsets(&mut self) {
+        let viewport = self.viewport_size();
+
+        for node_id in self.sticky_nodes.clone() {
+            let Some(node) = self.nodes.get(node_id) else {
+                continue;
+            };
+            let page_position = self.page_position(node_id, |node| &node.final_layout);
+            let height = node.final_layout.size.height;
+
+            // The element sticks to the padding box of its scroll container, or to the viewport. Both are measured
+            // from the top of the page, ignoring any scrolling, which is taken off the element's position instead.
+            let (view_top, view_height, scroll) = match self.scroll_container(node_id) {
+                Some(container_id) => {
+                    let container = &self.nodes[container_id];
+                    let layout = &container.final_layout;
+                    (
+                        self.page_position(container_id, |node| &node.final_layout)
+                            .y
+                            + layout.border.top,
+                        layout.size.height - layout.border.top - layout.border.bottom,
+                        container.scroll_offset.y,
+                    )
+                }
+                None => (0.0, viewport.height, self.viewport_scroll as f32),
+            };
+
+            let Some(style) = node.primary_styles() else {
+                continue;
+            };
+            let position = style.get_position();
+            let top = stylo_to_taffy::length_percentage_auto(&position.top)
+                .resolve_to_option(view_height);
+            let bottom = stylo_to_taffy::length_percentage_auto(&position.bottom)
+                .resolve_to_option(view_height);
+            drop(style);
+
+            // Where the element is within the scrollport before it's moved
+            let top_in_view = page_position.y - scroll - view_top;
+
+            let mut offset: f32 = 0.0;
+            if let Some(bottom) = bottom {
+                offset = offset.min(view_height - bottom - (top_in_view + height));
+            }
+            if let Some(top) = top {
+                offset = offset.max(top - top_in_view);
+            }
+
+            // ...but it never leaves its containing block's content box
+            if let Some(block_id) = self.sticky_containing_block(node_id) {
+                let layout = &self.nodes[block_id].final_layout;
+                let content_top = layout.padding.top + layout.border.top;
+                let content_bottom =
+                    layout.size.height - layout.padding.bottom - layout.border.bottom;
+                let y = page_position.y - self.page_position(block_id, |node| &node.final_layout).y;
+                offset = offset
+                    .min(content_bottom - (y + height))
+                    .max(content_top - y);
+            }
+
+            self.nodes[node_id].sticky_offset = Point { x: 0.0, y: offset };
+        }
+    }
+
+    /// The nearest ancestor of a node that scrolls its contents, if it's in one
+    fn scroll_container(&self, node_id: usize) -> Option<usize> {
+        let node = &self.nodes[node_id];
+        let mut current = node.layout_parent.or(node.parent);
+        while let Some(id) = current {
+            let ancestor = &self.nodes[id];
+            if ancestor.is_scroll_container() {
+                return Some(id);
+            }
+            current = ancestor.layout_parent.or(ancestor.parent);
+        }
+        None
+    }
+
+    /// The box a sticky element has to stay inside. That's its parent, except that table cells stick within the whole
+    /// table rather than their row (tables are laid out as blocks, so a row is only as tall as its cells).
+    fn sticky_containing_block(&self, node_id: usize) -> Option<usize> {
+        let node = &self.nodes[node_id];
+        let mut current = node.layout_parent.or(node.parent);
+        while let Some(id) = current {
+            let ancestor = &self.nodes[id];
+            let is_table_part = ancestor.primary_styles().map_or(false, |style| {
+                style.clone_display().outside() == DisplayOutside::InternalTable
+            });
+            if !is_table_part {
+                return Some(id);
+            }
+            current = ancestor.layout_parent.or(ancestor.parent);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Document;
+
+    #[test]
+    fn fixed_nodes_without_insets_stay_put() {
+        let mut doc = Document::from_html_for_test(
+            r#"
+            <body style="margin: 0">
+                <div style="padding: 10px">
+                    <div style="height: 50px"></div>
+                    <div id="fixed" style="position: fixed; width: 20px; height: 20px"></div>
+                </div>
+            </body>
+            "#,
+        );
+        let fixed = doc.element_by_id_for_test("fixed");
+        let location = doc.nodes[fixed].unrounded_layout.location;
+        assert_eq!((location.x, location.y), (10.0, 60.0));
+
+        // Laying out again with everything cached must not move it
+        doc.resolve();
+        doc.resolve();
+        let relaid = doc.nodes[fixed].unrounded_layout.location;
+        assert_eq!((relaid.x, relaid.y), (10.0, 60.0));
+    }
+
+    #[test]
+    fn sticky_nodes_stick_to_their_scroll_container() {
+        let mut doc = Document::from_html_for_test(
+            r#"
+            <style>
+                table { display: table }
+                thead, tbody { display: table-row-group }
+                tr { display: table-row }
+                th, td { display: table-cell }
+            </style>
+            <body style="margin: 0">
+                <div style="height: 30px"></div>
+                <div id="wrapper" style="overflow: auto; height: 100px">
+                    <table>
+                        <thead><tr><th id="header" style="position: sticky; top: 0; height: 20px"></th></tr></thead>
+                        <tbody><tr><td id="cell" style="height: 500px"></td></tr></tbody>
+                    </table>
+                </div>
+            </body>
+            "#,
+        );
+        let wrapper = doc.element_by_id_for_test("wrapper");
+        let header = doc.element_by_id_for_test("header");
+        let cell = doc.element_by_id_for_test("cell");
+
+        doc.scroll_node_by(Some(cell), 0.0, 50.0);
+        assert_eq!(doc.nodes[wrapper].scroll_offset.y, 50.0);
+
+        // The header stays at the top of the wrapper, rather than scrolling out of it with its row
+        let wrapper_top = doc.page_position(wrapper, |node| &node.final_layout).y;
+        let header_top = doc.page_position(header, |node| &node.final_layout).y - 50.0
+            + doc.nodes[header].sticky_offset.y;
+        assert_eq!(header_top, wrapper_top);
+    }
+}
@@ -11,6 +11,9 @@
 /// This is the primary entry point for this crate.
 pub mod document;
 
+/// Events dispatched from the renderer to the document
+pub mod events;
+
 /// An implementation for Html5ever's sink trait, allowing us to parse HTML into a DOM.
 pub mod htmlsink;
 
@@ -20,6 +23,9 @@ pub mod layout;
 /// A collection of methods for manipulating the DOM.
 pub mod mutation;
 
+/// Fetching linked resources (images, stylesheets) in the background
+pub mod net;
+
 /// The nodes themsleves, and their data.
 ///
 /// todo: we want this to use ECS, but we're not done with the design yet.
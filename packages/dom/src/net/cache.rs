@@ -0,0 +1,176 @@
+//! An on-disk cache for HTTP responses
+//!
+//! Each response is stored as two files named after a hash of its url: the body, and a small `name: value` metadata
+//! file holding the validators (`ETag` and `Last-Modified`) and when the response goes stale. Fresh responses are
+//! served without touching the network, and stale ones are revalidated with a conditional request.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+pub(crate) struct DiskCache {
+    dir: PathBuf,
+}
+
+/// Everything we keep about a cached response besides its body
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct CacheEntry {
+    pub(crate) content_type: Option<String>,
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+    /// Seconds since the unix epoch after which the response has to be revalidated
+    pub(crate) expires: u64,
+}
+
+impl CacheEntry {
+    /// Read the caching headers of a response. Returns `None` if it mustn't (or can't usefully) be cached.
+    pub(crate) fn from_response(response: &ureq::Response) -> Option<Self> {
+        let mut no_cache = false;
+        let mut max_age = None;
+        for directive in response
+            .all("Cache-Control")
+            .into_iter()
+            .flat_map(|value| value.split(','))
+        {
+            let directive = directive.trim().to_ascii_lowercase();
+            match directive.split_once('=') {
+                Some(("max-age", seconds)) => max_age = seconds.trim_matches('"').parse().ok(),
+                _ if directive == "no-store" => return None,
+                _ if directive == "no-cache" => no_cache = true,
+                _ => {}
+            }
+        }
+
+        let mut entry = Self {
+            content_type: response.header("Content-Type").map(String::from),
+            etag: response.header("ETag").map(String::from),
+            last_modified: response.header("Last-Modified").map(String::from),
+            expires: 0,
+        };
+        if !no_cache {
+            entry.refresh(max_age);
+        }
+
+        // Without a lifetime or a validator we'd have to fetch it again anyway
+        let useful = entry.is_fresh() || entry.etag.is_some() || entry.last_modified.is_some();
+        useful.then_some(entry)
+    }
+
+    pub(crate) fn is_fresh(&self) -> bool {
+        now() < self.expires
+    }
+
+    /// Start the response's lifetime over, e.g. after it has been revalidated
+    pub(crate) fn refresh(&mut self, max_age: Option<u64>) {
+        self.expires = max_age.map_or(0, |max_age| now().saturating_add(max_age));
+    }
+
+    fn to_meta(&self, url: &Url) -> String {
+        let mut meta = format!("url: {}\nexpires: {}\n", url, self.expires);
+        let headers = [
+            ("content-type", &self.content_type),
+            ("etag", &self.etag),
+            ("last-modified", &self.last_modified),
+        ];
+        for (name, value) in headers {
+            if let Some(value) = value {
+                meta.push_str(&format!("{}: {}\n", name, value));
+            }
+        }
+        meta
+    }
+
+    /// Parse the metadata file, checking that it belongs to `url` rather than to another url with the same hash
+    fn from_meta(meta: &str, url: &Url) -> Option<Self> {
+        let mut entry = Self::default();
+        let mut matches_url = false;
+        for line in meta.lines() {
+            let (name, value) = line.split_once(": ")?;
+            match name {
+                "url" => matches_url = value == url.as_str(),
+                "expires" => entry.expires = value.parse().ok()?,
+                "content-type" => entry.content_type = Some(value.to_string()),
+                "etag" => entry.etag = Some(value.to_string()),
+                "last-modified" => entry.last_modified = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        matches_url.then_some(entry)
+    }
+}
+
+impl DiskCache {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub(crate) fn get(&self, url: &Url) -> Option<(CacheEntry, Vec<u8>)> {
+        let (meta_path, body_path) = self.paths(url);
+        let entry = CacheEntry::from_meta(&fs::read_to_string(meta_path).ok()?, url)?;
+        let body = fs::read(body_path).ok()?;
+        Some((entry, body))
+    }
+
+    /// Save a response. The cache is only an optimisation, so failing to write to it isn't an error.
+    pub(crate) fn put(&self, url: &Url, entry: &CacheEntry, body: &[u8]) {
+        let (meta_path, body_path) = self.paths(url);
+        let _ = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(body_path, body))
+            .and_then(|_| fs::write(meta_path, entry.to_meta(url)));
+    }
+
+    /// Update a response's metadata without rewriting its body
+    pub(crate) fn update(&self, url: &Url, entry: &CacheEntry) {
+        let (meta_path, _) = self.paths(url);
+        let _ = fs::write(meta_path, entry.to_meta(url));
+    }
+
+    fn paths(&self, url: &Url) -> (PathBuf, PathBuf) {
+        let key = format!("{:016x}", fxhash::hash64(url.as_str()));
+        (
+            self.dir.join(format!("{}.meta", key)),
+            self.dir.join(format!("{}.body", key)),
+        )
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_entries() {
+        let dir = std::env::temp_dir().join(format!("blitz-cache-test-{}", std::process::id()));
+        let cache = DiskCache::new(&dir);
+        let url = Url::parse("https://example.com/logo.png").unwrap();
+        let other_url = Url::parse("https://example.com/other.png").unwrap();
+
+        let mut entry = CacheEntry {
+            content_type: Some("image/png".to_string()),
+            etag: Some("\"abc\"".to_string()),
+            ..Default::default()
+        };
+        entry.refresh(Some(60));
+        cache.put(&url, &entry, b"png");
+
+        let (cached, body) = cache.get(&url).unwrap();
+        assert_eq!(cached, entry);
+        assert!(cached.is_fresh());
+        assert_eq!(body, b"png");
+        assert!(cache.get(&other_url).is_none());
+
+        entry.refresh(None);
+        cache.update(&url, &entry);
+        assert!(!cache.get(&url).unwrap().0.is_fresh());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,536 @@
+//! Fetching linked resources
+//!
+//! Everything a document links to is fetched through its [`NetProvider`], which can be swapped out to serve custom url
+//! schemes (e.g. assets embedded in an app) or to keep tests off the network.
+//!
+//! Fetches run on the Tokio runtime's blocking pool and send their results back over a channel. The document drains
+//! the channel when it's polled, and a finished fetch wakes whoever last polled it (the window's waker), so resources
+//! get applied and drawn as soon as they arrive. Images are fetched and decoded once per url, however many nodes link
+//! to them.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use image::DynamicImage;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use url::Url;
+
+use crate::text::{decode_font, FontFaceDescriptors};
+use crate::util::{fetch_blob, fetch_css, fetch_image};
+use cache::{CacheEntry, DiskCache};
+
+mod cache;
+
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:60.0) Gecko/20100101 Firefox/81.0";
+const FILE_SIZE_LIMIT: u64 = 1_000_000_000; // 1GB
+
+/// Somewhere to fetch resources from. Providers are called from background threads, and should return
+/// [`NetError::UnsupportedUrl`] for urls they don't handle so they can be combined with a [`ProviderChain`].
+///
+/// `data:` urls never reach the provider.
+pub trait NetProvider: Send + Sync {
+    fn fetch(&self, url: &Url) -> Result<Response, NetError>;
+}
+
+/// A fetched resource
+#[derive(Clone, Debug, Default)]
+pub struct Response {
+    pub bytes: Vec<u8>,
+    /// The full `Content-Type` (e.g. `text/css; charset=utf-8`), if the provider knows it
+    pub content_type: Option<String>,
+}
+
+impl From<Vec<u8>> for Response {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            content_type: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    /// The provider doesn't know how to fetch this kind of url
+    UnsupportedUrl(Url),
+    NotFound(Url),
+    Io(std::io::Error),
+    Http(Box<ureq::Error>),
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::UnsupportedUrl(url) => write!(f, "Unsupported url {}", url),
+            NetError::NotFound(url) => write!(f, "Not found: {}", url),
+            NetError::Io(err) => write!(f, "{}", err),
+            NetError::Http(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<std::io::Error> for NetError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<ureq::Error> for NetError {
+    fn from(value: ureq::Error) -> Self {
+        Self::Http(Box::new(value))
+    }
+}
+
+/// Why a linked resource couldn't be loaded. These are collected by the document (see [`Document::errors`]) rather
+/// than stopping it from rendering.
+///
+/// [`Document::errors`]: crate::Document::errors
+#[derive(Debug)]
+pub enum ResourceError {
+    InvalidUrl(String, url::ParseError),
+    InvalidDataUrl(Url),
+    Fetch(Url, NetError),
+    /// The resource was fetched but isn't an image we can decode
+    InvalidImage(Url, image::ImageError),
+    /// The resource was fetched but isn't a font we can load
+    InvalidFont(Url),
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceError::InvalidUrl(url, err) => write!(f, "Invalid url {}: {}", url, err),
+            ResourceError::InvalidDataUrl(url) => write!(f, "Invalid data url {}", url),
+            ResourceError::Fetch(url, err) => write!(f, "Error fetching {}: {}", url, err),
+            ResourceError::InvalidImage(url, err) => {
+                write!(f, "Error decoding image {}: {}", url, err)
+            }
+            ResourceError::InvalidFont(url) => write!(f, "Error loading font {}", url),
+        }
+    }
+}
+
+impl std::error::Error for ResourceError {}
+
+/// Fetches `http:` and `https:` urls from the network
+pub struct HttpProvider {
+    user_agent: String,
+    cache: Option<DiskCache>,
+}
+
+impl Default for HttpProvider {
+    fn default() -> Self {
+        Self::new(USER_AGENT)
+    }
+}
+
+impl HttpProvider {
+    pub fn new(user_agent: impl Into<String>) -> Self {
+        Self {
+            user_agent: user_agent.into(),
+            cache: None,
+        }
+    }
+
+    /// Keep responses in `dir`, honouring their `Cache-Control` and revalidating them with their `ETag` or
+    /// `Last-Modified` once they go stale
+    pub fn with_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache = Some(DiskCache::new(dir));
+        self
+    }
+}
+
+impl NetProvider for HttpProvider {
+    fn fetch(&self, url: &Url) -> Result<Response, NetError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(NetError::UnsupportedUrl(url.clone()));
+        }
+
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(url));
+        let mut request = ureq::get(url.as_str()).set("User-Agent", &self.user_agent);
+        if let Some((entry, bytes)) = &cached {
+            if entry.is_fresh() {
+                return Ok(Response {
+                    bytes: bytes.clone(),
+                    content_type: entry.content_type.clone(),
+                });
+            }
+            if let Some(etag) = &entry.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+
+        let resp = request.call()?;
+        let entry = CacheEntry::from_response(&resp);
+
+        // Our copy is still good, and the server might have given it a new lifetime
+        if let (304, Some((mut cached_entry, bytes)), Some(cache)) =
+            (resp.status(), cached, &self.cache)
+        {
+            if let Some(entry) = entry {
+                cached_entry.expires = entry.expires;
+                cache.update(url, &cached_entry);
+            }
+            return Ok(Response {
+                bytes,
+                content_type: cached_entry.content_type,
+            });
+        }
+
+        let len: usize = resp
+            .header("Content-Length")
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(0);
+        let content_type = resp.header("Content-Type").map(String::from);
+        let mut bytes: Vec<u8> = Vec::with_capacity(len);
+
+        resp.into_reader()
+            .take(FILE_SIZE_LIMIT)
+            .read_to_end(&mut bytes)?;
+
+        if let (Some(cache), Some(entry)) = (&self.cache, &entry) {
+            cache.put(url, entry, &bytes);
+        }
+
+        Ok(Response {
+            bytes,
+            content_type,
+        })
+    }
+}
+
+/// Reads `file:` urls from the local filesystem
+#[derive(Default)]
+pub struct FileProvider;
+
+impl NetProvider for FileProvider {
+    fn fetch(&self, url: &Url) -> Result<Response, NetError> {
+        let path = match url.scheme() {
+            "file" => url.to_file_path(),
+            _ => Err(()),
+        };
+        let Ok(path) = path else {
+            return Err(NetError::UnsupportedUrl(url.clone()));
+        };
+
+        std::fs::read(path)
+            .map(Response::from)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::NotFound => NetError::NotFound(url.clone()),
+                _ => NetError::Io(err),
+            })
+    }
+}
+
+/// Serves resources from memory, keyed by their full url. Useful for tests that shouldn't touch the network.
+/// Urls it doesn't have are unsupported, so that a [`ProviderChain`] falls through to its next provider.
+#[derive(Default)]
+pub struct MemoryProvider {
+    resources: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, url: &str, data: impl Into<Vec<u8>>) -> Self {
+        self.insert(url, data);
+        self
+    }
+
+    pub fn insert(&mut self, url: &str, data: impl Into<Vec<u8>>) {
+        // Normalise the url so that lookups match however it was written
+        let url = Url::parse(url).map_or_else(|_| url.to_string(), String::from);
+        self.resources.insert(url, data.into());
+    }
+}
+
+impl NetProvider for MemoryProvider {
+    fn fetch(&self, url: &Url) -> Result<Response, NetError> {
+        self.resources
+            .get(url.as_str())
+            .map(|data| Response::from(data.clone()))
+            .ok_or_else(|| NetError::UnsupportedUrl(url.clone()))
+    }
+}
+
+/// Serves assets compiled into the app (e.g. with `include_bytes!`) under a custom url scheme, so that
+/// `asset://images/logo.png` can be registered as `"images/logo.png"`
+pub struct EmbeddedProvider {
+    scheme: String,
+    assets: HashMap<String, &'static [u8]>,
+}
+
+impl EmbeddedProvider {
+    pub fn new(scheme: impl Into<String>) -> Self {
+        Self {
+            scheme: scheme.into(),
+            assets: HashMap::new(),
+        }
+    }
+
+    pub fn with(mut self, path: &str, data: &'static [u8]) -> Self {
+        self.insert(path, data);
+        self
+    }
+
+    pub fn insert(&mut self, path: &str, data: &'static [u8]) {
+        self.assets
+            .insert(path.trim_start_matches('/').to_string(), data);
+    }
+}
+
+impl NetProvider for EmbeddedProvider {
+    fn fetch(&self, url: &Url) -> Result<Response, NetError> {
+        if url.scheme() != self.scheme {
+            return Err(NetError::UnsupportedUrl(url.clone()));
+        }
+
+        // The first path segment of `asset://images/logo.png` is parsed as the url's host
+        let path = format!("{}{}", url.host_str().unwrap_or(""), url.path());
+        self.assets
+            .get(path.trim_start_matches('/'))
+            .map(|data| Response::from(data.to_vec()))
+            .ok_or_else(|| NetError::NotFound(url.clone()))
+    }
+}
+
+/// Tries each provider in turn until one of them supports the url
+pub struct ProviderChain(pub Vec<Arc<dyn NetProvider>>);
+
+/// Local files and HTTP, which is what documents use unless they're given another provider
+impl Default for ProviderChain {
+    fn default() -> Self {
+        Self(vec![
+            Arc::new(FileProvider),
+            Arc::new(HttpProvider::default()),
+        ])
+    }
+}
+
+impl NetProvider for ProviderChain {
+    fn fetch(&self, url: &Url) -> Result<Response, NetError> {
+        for provider in &self.0 {
+            match provider.fetch(url) {
+                Err(NetError::UnsupportedUrl(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(NetError::UnsupportedUrl(url.clone()))
+    }
+}
+
+/// A fetched resource, along with the node that linked to it
+pub(crate) enum Resource {
+    Image(usize, Arc<DynamicImage>),
+    Stylesheet(usize, String),
+    /// A font for an `@font-face` rule, unpacked to OpenType, along with the url it came from
+    Font(FontFaceDescriptors, Url, Vec<u8>),
+}
+
+/// What a background fetch sends back. Each fetch reports back even if it failed, so that we can tell when nothing
+/// is left loading.
+enum Fetched {
+    Image(Url, Result<Arc<DynamicImage>, ResourceError>),
+    Stylesheet(usize, Result<String, ResourceError>),
+    /// The first source that loaded (if any), after the errors from the sources tried before it
+    Font(
+        FontFaceDescriptors,
+        Option<(Url, Vec<u8>)>,
+        Vec<ResourceError>,
+    ),
+}
+
+/// Images are shared by every node that links to the same url, so that they're only fetched and decoded once
+enum CachedImage {
+    /// Still being fetched for these nodes
+    Loading(Vec<usize>),
+    Loaded(Arc<DynamicImage>),
+}
+
+pub(crate) struct ResourceLoader {
+    pub(crate) provider: Arc<dyn NetProvider>,
+    sender: UnboundedSender<Fetched>,
+    receiver: UnboundedReceiver<Fetched>,
+    /// Fetches that haven't reported back yet
+    pending: usize,
+    /// Keyed by resolved url
+    images: HashMap<Url, CachedImage>,
+    /// Resources that are ready to be applied to the document
+    ready: VecDeque<Result<Resource, ResourceError>>,
+    /// Whoever last polled for resources, to be woken when one is ready without needing a fetch
+    waker: Option<Waker>,
+}
+
+impl Default for ResourceLoader {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Self {
+            provider: Arc::new(ProviderChain::default()),
+            sender,
+            receiver,
+            pending: 0,
+            images: HashMap::new(),
+            ready: VecDeque::new(),
+            waker: None,
+        }
+    }
+}
+
+impl ResourceLoader {
+    pub(crate) fn fetch_image(&mut self, node_id: usize, url: Url) {
+        match self.images.get_mut(&url) {
+            Some(CachedImage::Loaded(image)) => {
+                let image = image.clone();
+                self.ready.push_back(Ok(Resource::Image(node_id, image)));
+                if let Some(waker) = &self.waker {
+                    waker.wake_by_ref();
+                }
+            }
+            Some(CachedImage::Loading(node_ids)) => node_ids.push(node_id),
+            None => {
+                self.images
+                    .insert(url.clone(), CachedImage::Loading(vec![node_id]));
+
+                let provider = self.provider.clone();
+                self.spawn(move || {
+                    let image = fetch_image(&*provider, &url).map(Arc::new);
+                    Fetched::Image(url, image)
+                });
+            }
+        }
+    }
+
+    pub(crate) fn fetch_stylesheet(&mut self, node_id: usize, url: Url) {
+        let provider = self.provider.clone();
+        self.spawn(move || Fetched::Stylesheet(node_id, fetch_css(&*provider, &url)));
+    }
+
+    /// Fetch a font for an `@font-face` rule from the first of its sources (listed in order of preference) that loads
+    pub(crate) fn fetch_font(&mut self, face: FontFaceDescriptors, sources: Vec<Url>) {
+        let provider = self.provider.clone();
+        self.spawn(move || {
+            let mut errors = Vec::new();
+            for url in sources {
+                match fetch_blob(&*provider, &url).map(|response| decode_font(response.bytes)) {
+                    Ok(Some(font)) => return Fetched::Font(face, Some((url, font)), errors),
+                    Ok(None) => errors.push(ResourceError::InvalidFont(url)),
+                    Err(error) => errors.push(error),
+                }
+            }
+            Fetched::Font(face, None, errors)
+        });
+    }
+
+    fn spawn(&mut self, fetch: impl FnOnce() -> Fetched + Send + 'static) {
+        self.pending += 1;
+
+        let sender = self.sender.clone();
+        let task = move || {
+            // Nobody is listening any more if the document was dropped while we were fetching
+            let _ = sender.send(fetch());
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(task);
+            }
+            // Outside of a runtime (e.g. when rendering headlessly) give each fetch its own thread
+            Err(_) => {
+                std::thread::spawn(task);
+            }
+        }
+    }
+
+    /// Whether every resource has been fetched and applied
+    pub(crate) fn is_idle(&self) -> bool {
+        self.pending == 0 && self.ready.is_empty()
+    }
+
+    /// Take the next resource that is ready to be applied. Returns `Pending` (and wakes `cx` later) if nothing has
+    /// finished yet.
+    pub(crate) fn poll_next(&mut self, cx: &mut Context) -> Poll<Result<Resource, ResourceError>> {
+        self.waker = Some(cx.waker().clone());
+        loop {
+            if let Some(resource) = self.ready.pop_front() {
+                return Poll::Ready(resource);
+            }
+            match self.receiver.poll_recv(cx) {
+                Poll::Ready(Some(fetched)) => {
+                    self.pending -= 1;
+                    self.receive(fetched);
+                }
+                // We hold a sender ourselves, so the channel never closes
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn receive(&mut self, fetched: Fetched) {
+        match fetched {
+            Fetched::Image(url, Ok(image)) => {
+                let loaded = CachedImage::Loaded(image.clone());
+                if let Some(CachedImage::Loading(node_ids)) = self.images.insert(url, loaded) {
+                    self.ready.extend(
+                        node_ids
+                            .into_iter()
+                            .map(|node_id| Ok(Resource::Image(node_id, image.clone()))),
+                    );
+                }
+            }
+            // Forget images that failed so that they're tried again if they're linked again
+            Fetched::Image(url, Err(error)) => {
+                self.images.remove(&url);
+                self.ready.push_back(Err(error));
+            }
+            Fetched::Stylesheet(node_id, css) => {
+                self.ready
+                    .push_back(css.map(|css| Resource::Stylesheet(node_id, css)));
+            }
+            Fetched::Font(face, font, errors) => {
+                self.ready.extend(errors.into_iter().map(Err));
+                if let Some((url, font)) = font {
+                    self.ready.push_back(Ok(Resource::Font(face, url, font)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_falls_through_unsupported_urls() {
+        let chain = ProviderChain(vec![
+            Arc::new(EmbeddedProvider::new("asset").with("images/logo.png", b"logo")),
+            Arc::new(MemoryProvider::new().with("https://example.com/style.css", "p {}")),
+        ]);
+
+        let fetch = |url: &str| chain.fetch(&Url::parse(url).unwrap());
+        assert_eq!(fetch("asset://images/logo.png").unwrap().bytes, b"logo");
+        assert_eq!(
+            fetch("https://example.com/style.css").unwrap().bytes,
+            b"p {}"
+        );
+        assert!(matches!(
+            fetch("asset://missing.png"),
+            Err(NetError::NotFound(_))
+        ));
+        assert!(matches!(
+            fetch("https://example.com/missing.css"),
+            Err(NetError::UnsupportedUrl(_))
+        ));
+    }
+}
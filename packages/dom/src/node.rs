@@ -4,9 +4,13 @@ use image::DynamicImage;
 use selectors::matching::QuirksMode;
 use slab::Slab;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
 use std::sync::Arc;
+use style::computed_values::position::T as Position;
+use style::invalidation::element::restyle_hints::RestyleHint;
 use style::properties::ComputedValues;
 use style::stylesheets::UrlExtraData;
+use style::values::specified::box_::DisplayInside;
 use style::Atom;
 use style::{
     data::ElementData,
@@ -15,12 +19,15 @@ use style::{
     shared_lock::{Locked, SharedRwLock},
     stylesheets::CssRuleType,
 };
+use style_traits::dom::ElementState;
 use taffy::{
     prelude::{Layout, Style},
-    Cache,
+    Cache, Overflow, Point,
 };
 use url::Url;
 
+use crate::text::{FontContext, TextLayout, TextStyle};
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DisplayOuter {
     Block,
@@ -41,6 +48,18 @@ pub struct Node {
     pub child_idx: usize,
     // What are our children?
     pub children: Vec<usize>,
+    /// Anonymous nodes generated for our `::before` and `::after` pseudo-elements. They aren't part of the DOM, so
+    /// they're only found here and in `layout_children`.
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+    /// Anonymous block boxes wrapping runs of inline-level children, for block containers that have both block-level
+    /// and inline-level children. Like `before` and `after` they aren't part of the DOM.
+    pub anonymous_blocks: Vec<usize>,
+    /// The children that are laid out and drawn: the DOM children (sorted by `order` in flex containers) between any
+    /// `::before` and `::after` nodes, with runs of inline children swapped for the anonymous blocks wrapping them
+    pub layout_children: Vec<usize>,
+    /// The node whose `layout_children` this node is in. Layouts are relative to it rather than to the parent.
+    pub layout_parent: Option<usize>,
 
     /// Node type (Element, TextNode, etc) specific data
     pub raw_dom_data: NodeData,
@@ -49,14 +68,40 @@ pub struct Node {
     // TODO: See if guard can be hoisted to a higher level
     pub stylo_element_data: AtomicRefCell<Option<ElementData>>,
     pub guard: SharedRwLock,
+    /// Interactive state (hover, active, focus) that pseudo-classes like `:hover` match against. States that follow
+    /// from attributes (e.g. `:disabled`) are read from the attributes instead, see [`Node::element_state`].
+    pub element_state: ElementState,
+    /// Whether any of this node's descendants need restyling. The style traversal skips subtrees that don't.
+    pub(crate) dirty_descendants: AtomicBool,
+    /// Whether the style traversal has a snapshot of this element from before its state or attributes changed, and
+    /// whether it has invalidated styles with it yet
+    pub(crate) has_snapshot: AtomicBool,
+    pub(crate) snapshot_handled: AtomicBool,
+    /// How many of this element's children the parallel style traversal has yet to finish with
+    pub(crate) children_to_process: AtomicIsize,
 
     // Taffy layout data:
     pub style: Style,
     pub hidden: bool,
     pub display_outer: DisplayOuter,
+    /// Whether this is a block container whose children are all inline-level, which we lay out ourselves rather than
+    /// with Taffy
+    pub is_inline_root: bool,
+    /// Whether `style` has calc() values that layout has to resolve against the containing block
+    pub(crate) has_calc: bool,
+    /// Positioned glyphs for text nodes, filled in by layout
+    pub text_layout: Option<TextLayout>,
     pub cache: Cache,
     pub unrounded_layout: Layout,
     pub final_layout: Layout,
+    /// Where layout placed the node within its parent. `position: fixed` elements are moved to be relative to the
+    /// viewport afterwards, but those without insets still need to know where they would have been.
+    pub static_position: Point<f32>,
+    /// How far a `position: sticky` element is moved from its place in the layout to stay in view as the page
+    /// scrolls
+    pub sticky_offset: Point<f32>,
+    /// How far the content of a scroll container has been scrolled
+    pub scroll_offset: Point<f32>,
 }
 
 /// The different kinds of nodes in the DOM.
@@ -153,6 +198,14 @@ impl ElementNodeData {
         Some(&attr.value)
     }
 
+    /// Whether this is an element that can be disabled
+    pub fn is_form_control(&self) -> bool {
+        matches!(
+            self.name.local.as_ref(),
+            "button" | "input" | "select" | "textarea" | "option" | "optgroup" | "fieldset"
+        )
+    }
+
     pub fn flush_style_attribute(&mut self, guard: &SharedRwLock) {
         self.style_attribute = self.attr(local_name!("style")).map(|style_str| {
             let url = UrlExtraData::from(
@@ -347,6 +400,31 @@ impl Node {
         }
     }
 
+    /// The styles used to measure and draw this node's text. Text nodes aren't styled themselves, so they use their
+    /// parent's styles.
+    pub fn text_style(&self, fonts: &FontContext) -> TextStyle {
+        let styled_node = match self.raw_dom_data {
+            NodeData::Text(_) => self.parent.map(|parent_id| self.with(parent_id)),
+            _ => Some(self),
+        };
+        styled_node
+            .and_then(|node| node.primary_styles())
+            .map(|style| TextStyle::from_computed(&style, fonts))
+            .unwrap_or_default()
+    }
+
+    /// Whether this is an inline-level element (e.g. `<span>`) whose contents take part in the surrounding inline
+    /// formatting context, rather than an atomic box like an `<img>` or `inline-block`
+    pub fn is_inline_box(&self) -> bool {
+        self.display_outer == DisplayOuter::Inline
+            && !self
+                .raw_dom_data
+                .is_element_with_tag_name(&local_name!("img"))
+            && self
+                .primary_styles()
+                .is_some_and(|style| style.get_box().display.inside() == DisplayInside::Flow)
+    }
+
     pub fn text_content(&self) -> String {
         let mut out = String::new();
         self.write_text_content(&mut out);
@@ -367,12 +445,150 @@ impl Node {
         }
     }
 
+    /// The state matched by pseudo-classes such as `:hover`, `:focus`, `:checked` and `:disabled`
+    pub fn element_state(&self) -> ElementState {
+        let mut state = self.element_state;
+        let Some(data) = self.element_data() else {
+            return state;
+        };
+
+        if data.is_form_control() {
+            match data.attr(local_name!("disabled")) {
+                Some(_) => state.insert(ElementState::DISABLED),
+                None => state.insert(ElementState::ENABLED),
+            }
+        }
+
+        // Everything else matches :read-only
+        if self.takes_text_input()
+            && data.attr(local_name!("readonly")).is_none()
+            && data.attr(local_name!("disabled")).is_none()
+        {
+            state.insert(ElementState::READWRITE);
+        }
+
+        let checked = match data.name.local.as_ref() {
+            "input" => data.attr(local_name!("checked")).is_some(),
+            "option" => data.attr(local_name!("selected")).is_some(),
+            _ => false,
+        };
+        if checked {
+            state.insert(ElementState::CHECKED);
+        }
+
+        state
+    }
+
+    /// Whether clicking this element gives it focus
+    pub fn is_focusable(&self) -> bool {
+        let Some(data) = self.element_data() else {
+            return false;
+        };
+        if data.is_form_control() && data.attr(local_name!("disabled")).is_some() {
+            return false;
+        }
+
+        match data.name.local.as_ref() {
+            "a" => data.attr(local_name!("href")).is_some(),
+            "button" | "select" | "textarea" => true,
+            "input" => data.attr(local_name!("type")) != Some("hidden"),
+            _ => data.attr(local_name!("tabindex")).is_some(),
+        }
+    }
+
+    /// Whether this is a control that can be typed into
+    pub fn takes_text_input(&self) -> bool {
+        let Some(data) = self.element_data() else {
+            return false;
+        };
+        match data.name.local.as_ref() {
+            "textarea" => true,
+            "input" => !matches!(
+                data.attr(local_name!("type")),
+                Some(
+                    "button"
+                        | "checkbox"
+                        | "color"
+                        | "file"
+                        | "hidden"
+                        | "image"
+                        | "radio"
+                        | "range"
+                        | "reset"
+                        | "submit"
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    /// Mark this node and its descendants as needing to be restyled on the next style resolution
+    pub fn mark_for_restyle(&self) {
+        if let Some(data) = self.stylo_element_data.borrow_mut().as_mut() {
+            data.hint |= RestyleHint::restyle_subtree();
+        }
+        self.mark_ancestors_dirty();
+    }
+
+    /// Make sure the next style traversal reaches this node
+    pub fn mark_ancestors_dirty(&self) {
+        let mut next = self.parent;
+        while let Some(parent_id) = next {
+            let parent = self.with(parent_id);
+            parent.dirty_descendants.store(true, Ordering::Relaxed);
+            next = parent.parent;
+        }
+    }
+
     pub fn flush_style_attribute(&mut self) {
         if let NodeData::Element(ref mut elem_data) = self.raw_dom_data {
             elem_data.flush_style_attribute(&self.guard);
         }
     }
 
+    /// Whether this is a `position: fixed` element, which is positioned against the viewport and doesn't scroll with
+    /// the page
+    pub fn is_fixed(&self) -> bool {
+        self.primary_styles()
+            .is_some_and(|style| style.get_box().clone_position() == Position::Fixed)
+    }
+
+    /// Whether this element has `overflow: scroll` or `overflow: auto` on either axis, so that its content can be
+    /// scrolled by the user
+    pub fn is_scroll_container(&self) -> bool {
+        self.style.overflow.x == Overflow::Scroll || self.style.overflow.y == Overflow::Scroll
+    }
+
+    /// Whether content that overflows this element is clipped to its padding box, i.e. its overflow isn't `visible`
+    pub fn clips_overflow(&self) -> bool {
+        self.style.overflow.x != Overflow::Visible || self.style.overflow.y != Overflow::Visible
+    }
+
+    /// How far the content of a scroll container can be scrolled on each axis before it runs out
+    pub fn scroll_range(&self) -> Point<f32> {
+        // The content size is measured from the top-left of the border box, and the content is scrolled within the
+        // padding box
+        let layout = &self.final_layout;
+        let range = |overflow, content: f32, size: f32, border_end: f32| match overflow {
+            Overflow::Scroll => (content - (size - border_end)).max(0.0),
+            _ => 0.0,
+        };
+        Point {
+            x: range(
+                self.style.overflow.x,
+                layout.content_size.width,
+                layout.size.width,
+                layout.border.right,
+            ),
+            y: range(
+                self.style.overflow.y,
+                layout.content_size.height,
+                layout.size.height,
+                layout.border.bottom,
+            ),
+        }
+    }
+
     pub fn order(&self) -> i32 {
         self.stylo_element_data
             .borrow()
@@ -391,8 +607,8 @@ impl Node {
     /// TODO: z-index
     /// (If multiple children are positioned at the position then a random one will be recursed into)
     pub fn hit(&self, x: f32, y: f32) -> Option<usize> {
-        let x = x - self.final_layout.location.x;
-        let y = y - self.final_layout.location.y;
+        let x = x - self.final_layout.location.x - self.sticky_offset.x;
+        let y = y - self.final_layout.location.y - self.sticky_offset.y;
 
         let size = self.final_layout.size;
         if x < 0.0 || x > size.width || y < 0.0 || y > size.height {
@@ -400,9 +616,14 @@ impl Node {
         }
 
         // Call `.hit()` on each child in turn. If any return `Some` then return that value. Else return `Some(self.id).
-        self.children
+        // Fixed elements aren't positioned relative to their parent, so the document hit tests them separately
+        let x = x + self.scroll_offset.x;
+        let y = y + self.scroll_offset.y;
+        self.layout_children
             .iter()
-            .find_map(|&i| self.with(i).hit(x, y))
+            .map(|&i| self.with(i))
+            .filter(|child| !child.is_fixed())
+            .find_map(|child| child.hit(x, y))
             .or(Some(self.id))
     }
 }
@@ -3,17 +3,20 @@
 
 use crate::node::{DisplayOuter, Node};
 
-use crate::node::NodeData;
+use crate::node::{ElementNodeData, NodeData, TextNodeData};
 use atomic_refcell::{AtomicRef, AtomicRefMut};
-use html5ever::{local_name, LocalName, Namespace};
+use html5ever::{local_name, ns, LocalName, Namespace, QualName};
 use selectors::{
+    attr::NamespaceConstraint,
     matching::{ElementSelectorFlags, MatchingContext, VisitedHandlingMode},
     sink::Push,
     Element, OpaqueElement,
 };
 // use slab::Slab;
+use std::sync::atomic::Ordering;
+use style::attr::{AttrIdentifier, AttrValue};
+use style::computed_values::position::T as PositionProperty;
 use style::values::specified::box_::DisplayOutside;
-use style::values::specified::TextAlignKeyword;
 use style::CaseSensitivityExt;
 use style::{
     animation::DocumentAnimationSet,
@@ -22,25 +25,31 @@ use style::{
         SharedStyleContext, StyleContext,
     },
     dom::{LayoutIterator, NodeInfo, OpaqueNode, TDocument, TElement, TNode, TShadowRoot},
-    global_style_data::GLOBAL_STYLE_DATA,
+    global_style_data::{GLOBAL_STYLE_DATA, STYLE_THREAD_POOL},
+    invalidation::element::restyle_hints::RestyleHint,
     properties::{
         style_structs::{Box as BoxStyle, Position},
-        PropertyDeclarationBlock,
+        ComputedValues, PropertyDeclarationBlock,
+    },
+    selector_parser::{
+        NonTSPseudoClass, PseudoElement, RestyleDamage, SelectorImpl, ServoElementSnapshot,
     },
-    selector_parser::SelectorImpl,
     servo_arc::{Arc, ArcBorrow},
     shared_lock::{Locked, SharedRwLock, StylesheetGuards},
     thread_state::ThreadState,
     traversal::{DomTraversal, PerLevelTraversalData},
     traversal_flags::TraversalFlags,
-    values::{AtomIdent, GenericAtomIdent},
+    values::{
+        computed::counters::{Content, ContentItem},
+        AtomIdent, GenericAtomIdent,
+    },
     Atom,
 };
 use style_traits::dom::ElementState;
 use taffy::prelude::Style;
 use taffy::Display;
 
-use super::stylo_to_taffy;
+use super::stylo_to_taffy::{self, GridLineNames};
 
 impl crate::document::Document {
     /// Walk the whole tree, converting styles to layout
@@ -50,25 +59,59 @@ impl crate::document::Document {
         parent: Option<usize>,
         parent_display: taffy::Display,
     ) {
+        // Grid items can be placed against the named lines and areas of their grid container
+        let (row_names, column_names) = parent
+            .filter(|_| parent_display == Display::Grid)
+            .and_then(|parent_id| self.nodes[parent_id].primary_styles())
+            .map_or_else(Default::default, |style| {
+                let position = style.get_position();
+                let areas = &position.grid_template_areas;
+                (
+                    GridLineNames::new(&position.grid_template_rows, areas, true),
+                    GridLineNames::new(&position.grid_template_columns, areas, false),
+                )
+            });
+
         // make a floating element
         for child in children.iter() {
-            let (display, mut children) = {
+            self.flush_pseudo_elements(*child);
+
+            let (display, mut children, needs_layout) = {
                 let node = self.nodes.get_mut(*child).unwrap();
+                // Stylo records what a restyle changed as damage. Anything more than a repaint can change layout.
+                let damage = node
+                    .stylo_element_data
+                    .borrow_mut()
+                    .as_mut()
+                    .map_or(RestyleDamage::empty(), |data| {
+                        std::mem::replace(&mut data.damage, RestyleDamage::empty())
+                    });
+                let needs_layout = !(damage - RestyleDamage::REPAINT).is_empty();
+
                 let stylo_element_data = node.stylo_element_data.borrow();
                 let primary_styles = stylo_element_data
                     .as_ref()
                     .and_then(|data| data.styles.get_primary());
 
                 let Some(style) = primary_styles else {
-                    // HACK: hide whitespace-only text node children of flexbox and grid nodes from Taffy
+                    // HACK: hide whitespace-only text node children of flexbox and grid nodes from Taffy. This is
+                    // worked out again on every flush, as the text or its parent's display may have changed since.
                     if let NodeData::Text(data) = &node.raw_dom_data {
-                        node.display_outer = DisplayOuter::Inline;
                         let all_whitespace = data.content.chars().all(|c| c.is_whitespace());
-                        if all_whitespace
+                        let (display, display_outer) = if all_whitespace
                             && (parent_display == Display::Flex || parent_display == Display::Grid)
                         {
-                            node.style.display = taffy::Display::None;
-                            node.display_outer = DisplayOuter::None;
+                            (taffy::Display::None, DisplayOuter::None)
+                        } else {
+                            (Style::DEFAULT.display, DisplayOuter::Inline)
+                        };
+                        let changed = node.style.display != display;
+                        node.style.display = display;
+                        node.display_outer = display_outer;
+
+                        drop(stylo_element_data);
+                        if changed {
+                            self.invalidate_layout(*child);
                         }
                     }
 
@@ -103,7 +146,17 @@ impl crate::document::Document {
                     max_height,
                     // box_sizing,
                     column_gap,
+                    row_gap,
                     aspect_ratio,
+                    grid_template_rows,
+                    grid_template_columns,
+                    grid_auto_rows,
+                    grid_auto_columns,
+                    grid_auto_flow,
+                    grid_row_start,
+                    grid_row_end,
+                    grid_column_start,
+                    grid_column_end,
                     ..
                 } = style.get_position();
 
@@ -171,30 +224,60 @@ impl crate::document::Document {
                         y: stylo_to_taffy::overflow(*overflow_y),
                     },
                     aspect_ratio: stylo_to_taffy::aspect_ratio(*aspect_ratio),
-                    // TODO: we'll eventually want to support visible scrollbars
-                    // But we really ought to implement "overflow: auto" first
+                    // Scrollbars are drawn over the content, so they don't take up any space
                     scrollbar_width: 0.0,
 
                     gap: taffy::Size {
                         width: stylo_to_taffy::gap(column_gap),
-                        // TODO: Enable row-gap in servo configuration of stylo
-                        height: taffy::LengthPercentage::Length(0.0),
+                        height: stylo_to_taffy::gap(row_gap),
                     },
 
-                    // TODO: Enable CSS Grid properties in servo configuration of stylo
-                    //
-                    // justify_items
-                    // justify_self
-                    // grid_template_rows
-                    // grid_template_columns
-                    // grid_auto_rows
-                    // grid_auto_columns
-                    // grid_auto_flow
-                    // grid_row
-                    // grid_column
+                    grid_template_rows: stylo_to_taffy::grid_template_tracks(grid_template_rows),
+                    grid_template_columns: stylo_to_taffy::grid_template_tracks(
+                        grid_template_columns,
+                    ),
+                    grid_auto_rows: stylo_to_taffy::grid_auto_tracks(grid_auto_rows),
+                    grid_auto_columns: stylo_to_taffy::grid_auto_tracks(grid_auto_columns),
+                    grid_auto_flow: stylo_to_taffy::grid_auto_flow(*grid_auto_flow),
+                    grid_row: stylo_to_taffy::grid_placement(
+                        grid_row_start,
+                        grid_row_end,
+                        &row_names,
+                    ),
+                    grid_column: stylo_to_taffy::grid_placement(
+                        grid_column_start,
+                        grid_column_end,
+                        &column_names,
+                    ),
+
+                    // TODO: justify_items and justify_self
                     ..Style::DEFAULT
                 };
 
+                let has_calc = stylo_to_taffy::resolve_calc(
+                    style,
+                    &mut node.style,
+                    taffy::Size::NONE,
+                    taffy::FlexDirection::Row,
+                );
+                let has_container_calc = stylo_to_taffy::resolve_container_calc(
+                    style,
+                    &mut node.style,
+                    taffy::Size::NONE,
+                );
+                node.has_calc = has_calc || has_container_calc;
+
+                // Layout moves these once taffy has laid out the rest of the page
+                match position {
+                    PositionProperty::Fixed => self.fixed_nodes.push(*child),
+                    PositionProperty::Sticky => {
+                        // The insets of sticky elements only apply while scrolling
+                        node.style.inset = taffy::Rect::auto();
+                        self.sticky_nodes.push(*child);
+                    }
+                    _ => {}
+                }
+
                 node.display_outer = match stylo_display.outside() {
                     DisplayOutside::None => crate::node::DisplayOuter::None,
                     DisplayOutside::Inline => crate::node::DisplayOuter::Inline,
@@ -203,15 +286,31 @@ impl crate::document::Document {
                     DisplayOutside::InternalTable => crate::node::DisplayOuter::Block,
                 };
 
-                // Clear Taffy cache
-                // TODO: smarter cache invalidation
-                node.cache.clear();
+                node.is_inline_root = false;
 
                 // would like to change this not require a clone, but requires some refactoring
-                (display, node.children.clone())
+                let children = node
+                    .before
+                    .iter()
+                    .chain(&node.children)
+                    .chain(&node.after)
+                    .copied()
+                    .collect();
+                (display, children, needs_layout)
             };
 
-            if display == taffy::Display::Flex {
+            // Text is measured with its parent's styles, so it has to be relaid out along with it
+            if needs_layout {
+                self.invalidate_layout(*child);
+                for child_id in &children {
+                    let child = &mut self.nodes[*child_id];
+                    if child.is_text_node() {
+                        child.cache.clear();
+                    }
+                }
+            }
+
+            if display == taffy::Display::Flex || display == taffy::Display::Grid {
                 // Reorder the children based on their flex order
                 // Would like to not have to
                 children.sort_by(|left, right| {
@@ -219,90 +318,281 @@ impl crate::document::Document {
                     let right_node = self.nodes.get(*right).unwrap();
                     left_node.order().cmp(&right_node.order())
                 });
-
-                // Mutate source child array
-                self.nodes.get_mut(*child).unwrap().children = children.clone();
             }
 
-            self.flush_styles_to_layout(children, Some(*child), display);
+            // The children's display is only known once they've been flushed
+            self.flush_styles_to_layout(children.clone(), Some(*child), display);
+            let children = self.flush_anonymous_blocks(*child, children, display);
+
+            for child_id in &children {
+                self.nodes[*child_id].layout_parent = Some(*child);
+            }
+            let node = &mut self.nodes[*child];
+            if node.layout_children != children {
+                node.layout_children = children;
+                self.invalidate_layout(*child);
+            }
         }
 
-        // HACK: render block nodes with all inline (or hidden) children using
-        // a flexbox wrapped row with start alignment in both axis.
+        // Block containers with only inline-level children establish an inline formatting context, which we lay out
+        // ourselves. Inline boxes like <span> don't: their children belong to the nearest such container.
         if let Some(parent_id) = parent {
             if parent_display == taffy::Display::Block {
-                if children
+                let all_inline = children
                     .iter()
                     .map(|cid| self.nodes.get(*cid).unwrap())
-                    .all(|c| c.display_outer != DisplayOuter::Block)
-                {
-                    let node = self.nodes.get_mut(parent_id).unwrap();
-                    node.style.display = taffy::Display::Flex;
-                    node.style.flex_direction = taffy::FlexDirection::Row;
-                    node.style.flex_wrap = taffy::FlexWrap::Wrap;
-                    node.style.align_items = Some(taffy::AlignItems::Start);
-                    node.style.justify_content = Some(taffy::JustifyContent::Start);
-
-                    // HACK: Set flexbox alignment based on value of text-align property
-                    // This fixes some but not all <center> tags
-                    let stylo_element_data = node.stylo_element_data.borrow();
-                    let primary_styles = stylo_element_data
-                        .as_ref()
-                        .and_then(|data| data.styles.get_primary());
-                    if let Some(style) = primary_styles {
-                        node.style.justify_content = Some(match style.clone_text_align() {
-                            TextAlignKeyword::Start => taffy::JustifyContent::Start,
-                            TextAlignKeyword::Left => taffy::JustifyContent::Start,
-                            TextAlignKeyword::Right => taffy::JustifyContent::End,
-                            TextAlignKeyword::Center => taffy::JustifyContent::Center,
-                            TextAlignKeyword::Justify => taffy::JustifyContent::SpaceBetween,
-                            TextAlignKeyword::End => taffy::JustifyContent::End,
-                            TextAlignKeyword::ServoCenter => taffy::JustifyContent::Center,
-                            TextAlignKeyword::ServoLeft => taffy::JustifyContent::Start,
-                            TextAlignKeyword::ServoRight => taffy::JustifyContent::End,
-                        });
-                    }
+                    .all(|c| c.display_outer != DisplayOuter::Block);
+                let node = self.nodes.get_mut(parent_id).unwrap();
+                node.is_inline_root = all_inline && !node.is_inline_box();
+            }
+        }
+    }
+
+    /// Block containers with both block-level and inline-level children wrap each run of inline-level children in an
+    /// anonymous block box, which lays the run out as an inline formatting context. Returns the children to lay out
+    /// in place of `children`.
+    fn flush_anonymous_blocks(
+        &mut self,
+        node_id: usize,
+        children: Vec<usize>,
+        display: Display,
+    ) -> Vec<usize> {
+        let is_block = |id: &usize| self.nodes[*id].display_outer == DisplayOuter::Block;
+        // Runs of collapsible whitespace (or hidden elements) between blocks don't need a box of their own
+        let is_inline_content = |id: &usize| {
+            let child = &self.nodes[*id];
+            match &child.raw_dom_data {
+                NodeData::Text(data) => !data.content.chars().all(char::is_whitespace),
+                _ => child.display_outer == DisplayOuter::Inline,
+            }
+        };
+        let node = &self.nodes[node_id];
+        let is_mixed = display == Display::Block
+            && !node.is_inline_box()
+            && children.iter().any(is_block)
+            && children.iter().any(is_inline_content);
+
+        let mut runs = Vec::new();
+        let mut layout_children = Vec::new();
+        if is_mixed {
+            let mut run = Vec::new();
+            for child_id in children {
+                if is_block(&child_id) {
+                    flush_run(&mut run, &mut runs, &mut layout_children, is_inline_content);
+                    layout_children.push(child_id);
+                } else {
+                    run.push(child_id);
+                }
+            }
+            flush_run(&mut run, &mut runs, &mut layout_children, is_inline_content);
+        } else {
+            layout_children = children;
+        }
 
-                    drop(stylo_element_data);
+        // Reuse the anonymous blocks from last time, so that their layout caches survive
+        let mut anonymous_blocks = std::mem::take(&mut self.nodes[node_id].anonymous_blocks);
+        for extra in anonymous_blocks.drain(runs.len().min(anonymous_blocks.len())..) {
+            self.remove_subtree(extra);
+        }
+        if runs.is_empty() {
+            return layout_children;
+        }
 
-                    for cid in children.iter() {
-                        let child = self.nodes.get_mut(*cid).unwrap();
+        let style = {
+            let parent_style = self.nodes[node_id].primary_styles();
+            let guard = &self.guard;
+            let guards = StylesheetGuards {
+                author: &guard.read(),
+                ua_or_user: &guard.read(),
+            };
+            parent_style.map(|parent_style| {
+                self.stylist.style_for_anonymous::<&Node>(
+                    &guards,
+                    &PseudoElement::ServoAnonymousBox,
+                    &parent_style,
+                )
+            })
+        };
 
-                        if child.display_outer != DisplayOuter::Block {
-                            continue;
-                        }
+        for (idx, (position, run)) in runs.into_iter().enumerate() {
+            let block_id = match anonymous_blocks.get(idx) {
+                Some(block_id) => *block_id,
+                None => {
+                    let block_id = self.create_node(NodeData::Element(ElementNodeData {
+                        name: QualName::new(None, ns!(html), LocalName::from("::anonymous-block")),
+                        id: None,
+                        attrs: Vec::new(),
+                        style_attribute: None,
+                        image: None,
+                        template_contents: None,
+                    }));
+                    self.nodes[block_id].parent = Some(node_id);
+                    anonymous_blocks.push(block_id);
+                    block_id
+                }
+            };
+            layout_children[position] = block_id;
 
-                        let stylo_element_data = child.stylo_element_data.borrow();
-                        let primary_styles = stylo_element_data
-                            .as_ref()
-                            .and_then(|data| data.styles.get_primary());
-
-                        if let Some(style) = primary_styles {
-                            use style::values::generics::box_::VerticalAlign;
-                            use style::values::generics::box_::VerticalAlignKeyword;
-                            match style.clone_vertical_align() {
-                                VerticalAlign::Keyword(keyword) => {
-                                    child.style.align_self = Some(match keyword {
-                                        VerticalAlignKeyword::Baseline => {
-                                            taffy::AlignSelf::Baseline
-                                        }
-                                        VerticalAlignKeyword::Sub => taffy::AlignSelf::End,
-                                        VerticalAlignKeyword::Super => taffy::AlignSelf::Start,
-                                        VerticalAlignKeyword::Top => taffy::AlignSelf::Start,
-                                        VerticalAlignKeyword::TextTop => taffy::AlignSelf::Start,
-                                        VerticalAlignKeyword::Middle => taffy::AlignSelf::Center,
-                                        VerticalAlignKeyword::Bottom => taffy::AlignSelf::End,
-                                        VerticalAlignKeyword::TextBottom => taffy::AlignSelf::End,
-                                    });
-                                }
-                                VerticalAlign::Length(length_percentage) => {
-                                    child.style.margin.top =
-                                        stylo_to_taffy::length_percentage(&length_percentage).into()
-                                }
-                            };
-                        }
-                    }
+            for child_id in &run {
+                self.nodes[*child_id].layout_parent = Some(block_id);
+            }
+            let block = &mut self.nodes[block_id];
+            if let Some(style) = &style {
+                let mut data = block.stylo_element_data.borrow_mut();
+                data.get_or_insert_with(Default::default).styles.primary = Some(style.clone());
+            }
+            block.style = Style {
+                display: Display::Block,
+                ..Style::DEFAULT
+            };
+            block.display_outer = DisplayOuter::Block;
+            block.is_inline_root = true;
+            if block.layout_children != run {
+                block.layout_children = run;
+                self.invalidate_layout(block_id);
+            }
+        }
+
+        self.nodes[node_id].anonymous_blocks = anonymous_blocks;
+        layout_children
+    }
+
+    /// Create, update or remove the anonymous nodes that hold an element's `::before` and `::after` content. Stylo
+    /// computes their styles along with the element's, and only if they have any content.
+    fn flush_pseudo_elements(&mut self, node_id: usize) {
+        for pseudo in [PseudoElement::Before, PseudoElement::After] {
+            let node = &self.nodes[node_id];
+            let pseudo_id = match pseudo {
+                PseudoElement::Before => node.before,
+                _ => node.after,
+            };
+            let style = node
+                .stylo_element_data
+                .borrow()
+                .as_ref()
+                .and_then(|data| data.styles.pseudos.get(&pseudo).cloned())
+                .filter(|style| !style.get_box().display.is_none());
+
+            let Some(style) = style else {
+                if let Some(pseudo_id) = pseudo_id {
+                    self.remove_subtree(pseudo_id);
+                    self.set_pseudo_node(node_id, &pseudo, None);
                 }
+                continue;
+            };
+            let text = generated_text(node, &style);
+
+            let pseudo_id = pseudo_id.unwrap_or_else(|| {
+                let name = match pseudo {
+                    PseudoElement::Before => "::before",
+                    _ => "::after",
+                };
+                let pseudo_id = self.create_node(NodeData::Element(ElementNodeData {
+                    name: QualName::new(None, ns!(html), LocalName::from(name)),
+                    id: None,
+                    attrs: Vec::new(),
+                    style_attribute: None,
+                    image: None,
+                    template_contents: None,
+                }));
+                let text_id = self.create_node(NodeData::Text(TextNodeData {
+                    content: String::new(),
+                }));
+                self.nodes[text_id].parent = Some(pseudo_id);
+                self.nodes[pseudo_id].children.push(text_id);
+                self.nodes[pseudo_id].parent = Some(node_id);
+                self.set_pseudo_node(node_id, &pseudo, Some(pseudo_id));
+                pseudo_id
+            });
+
+            // The anonymous node is never visited by the style traversal, so hand it the styles stylo computed
+            let pseudo_node = &self.nodes[pseudo_id];
+            let style_changed = {
+                let mut data = pseudo_node.stylo_element_data.borrow_mut();
+                let data = data.get_or_insert_with(Default::default);
+                let old_style = data.styles.primary.replace(style.clone());
+                !old_style.is_some_and(|old_style| Arc::ptr_eq(&old_style, &style))
+            };
+
+            let text_id = pseudo_node.children[0];
+            let NodeData::Text(text_data) = &mut self.nodes[text_id].raw_dom_data else {
+                unreachable!()
+            };
+            let text_changed = text_data.content != text;
+            text_data.content = text;
+
+            if style_changed || text_changed {
+                self.invalidate_layout(text_id);
+            }
+        }
+    }
+
+    fn set_pseudo_node(
+        &mut self,
+        node_id: usize,
+        pseudo: &PseudoElement,
+        pseudo_id: Option<usize>,
+    ) {
+        let node = &mut self.nodes[node_id];
+        match pseudo {
+            PseudoElement::Before => node.before = pseudo_id,
+            _ => node.after = pseudo_id,
+        }
+    }
+
+    /// Record an element's state and attributes before they change, so that the next restyle only restyles the
+    /// elements whose styles depend on what changed. Elements that haven't been styled yet don't need one.
+    pub(crate) fn snapshot_node(&mut self, node_id: usize) -> Option<&mut ServoElementSnapshot> {
+        let node = self.nodes.get(node_id)?;
+        let has_styles = node
+            .stylo_element_data
+            .borrow()
+            .as_ref()
+            .is_some_and(|data| data.has_styles());
+        if !has_styles {
+            return None;
+        }
+
+        // The traversal has to reach the element for the snapshot to be used
+        node.mark_ancestors_dirty();
+
+        let snapshot = self.pending_snapshots.entry(node_id).or_insert_with(|| {
+            let mut snapshot = ServoElementSnapshot::new();
+            snapshot.state = Some(node.element_state());
+            snapshot.attrs = Some(
+                node.attrs()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(snapshot_attr)
+                    .collect(),
+            );
+            snapshot
+        });
+        Some(snapshot)
+    }
+
+    /// Call before an element's attribute is set or removed
+    pub fn snapshot_attribute_change(&mut self, node_id: usize, name: &str) {
+        let name = LocalName::from(name);
+        let Some(snapshot) = self.snapshot_node(node_id) else {
+            return;
+        };
+
+        match name {
+            local_name!("id") => snapshot.id_changed = true,
+            local_name!("class") => snapshot.class_changed = true,
+            _ => snapshot.other_attributes_changed = true,
+        }
+        let is_style_attr = name == local_name!("style");
+        let name = GenericAtomIdent(name);
+        if !snapshot.changed_attrs.contains(&name) {
+            snapshot.changed_attrs.push(name);
+        }
+
+        // Inline styles don't come from selectors, so the snapshot can't tell that they changed
+        if is_style_attr {
+            if let Some(data) = self.nodes[node_id].stylo_element_data.borrow_mut().as_mut() {
+                data.hint.insert(RestyleHint::RESTYLE_SELF);
             }
         }
     }
@@ -310,6 +600,17 @@ impl crate::document::Document {
     pub fn resolve_stylist(&mut self) {
         style::thread_state::enter(ThreadState::LAYOUT);
 
+        // Hand the snapshots to Stylo, which finds them by the element's address
+        let snapshotted: Vec<usize> = self.pending_snapshots.keys().copied().collect();
+        for (node_id, snapshot) in self.pending_snapshots.drain() {
+            let Some(node) = self.nodes.get(node_id) else {
+                continue;
+            };
+            node.has_snapshot.store(true, Ordering::Relaxed);
+            node.snapshot_handled.store(false, Ordering::Relaxed);
+            self.snapshots.insert(TNode::opaque(&node), snapshot);
+        }
+
         let guard = &self.guard;
         let guards = StylesheetGuards {
             author: &guard.read(),
@@ -344,15 +645,81 @@ impl crate::document::Document {
         let token = RecalcStyle::pre_traverse(root, &context);
 
         if token.should_traverse() {
-            // Style the elements, resolving their data
+            // Style the elements, resolving their data. Stylo starts on this thread and hands subtrees off to its
+            // thread pool once there's enough work to go around.
             let traverser = RecalcStyle::new(context);
-            style::driver::traverse_dom(&traverser, token, None);
+            let pool = STYLE_THREAD_POOL.pool();
+            style::driver::traverse_dom(&traverser, token, pool.as_ref());
         }
 
+        for node_id in snapshotted {
+            if let Some(node) = self.nodes.get(node_id) {
+                node.has_snapshot.store(false, Ordering::Relaxed);
+                node.snapshot_handled.store(false, Ordering::Relaxed);
+            }
+        }
+        self.snapshots.clear();
+
         style::thread_state::exit(ThreadState::LAYOUT);
     }
 }
 
+/// End a run of inline-level children. Runs with content are replaced by a placeholder in `layout_children` that is
+/// later swapped for their anonymous block, and the rest are laid out as they are.
+fn flush_run(
+    run: &mut Vec<usize>,
+    runs: &mut Vec<(usize, Vec<usize>)>,
+    layout_children: &mut Vec<usize>,
+    is_inline_content: impl Fn(&usize) -> bool,
+) {
+    if run.iter().any(is_inline_content) {
+        runs.push((layout_children.len(), std::mem::take(run)));
+        layout_children.push(usize::MAX);
+    } else {
+        layout_children.append(run);
+    }
+}
+
+/// The text of a pseudo-element's `content`
+/// TODO: counters, images and the `quotes` property
+fn generated_text(element: &Node, style: &ComputedValues) -> String {
+    let Content::Items(items) = &style.get_counters().content else {
+        return String::new();
+    };
+
+    let mut text = String::new();
+    for item in items.items.iter() {
+        match item {
+            ContentItem::String(value) => text.push_str(value),
+            ContentItem::Attr(attr) => {
+                let name = LocalName::from(&*attr.attribute);
+                text.push_str(element.attr(name).unwrap_or_default());
+            }
+            ContentItem::OpenQuote => text.push('\u{201C}'),
+            ContentItem::CloseQuote => text.push('\u{201D}'),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// An attribute as Stylo's snapshots store it. Ids and classes are parsed so that the old values can be matched
+/// against selectors.
+fn snapshot_attr(attr: &crate::node::Attribute) -> (AttrIdentifier, AttrValue) {
+    let identifier = AttrIdentifier {
+        local_name: GenericAtomIdent(attr.name.local.clone()),
+        name: GenericAtomIdent(attr.name.local.clone()),
+        namespace: GenericAtomIdent(attr.name.ns.clone()),
+        prefix: attr.name.prefix.clone().map(GenericAtomIdent),
+    };
+    let value = match attr.name.local {
+        local_name!("id") => AttrValue::from_atomic(attr.value.clone()),
+        local_name!("class") => AttrValue::from_serialized_tokenlist(attr.value.clone()),
+        _ => AttrValue::String(attr.value.clone()),
+    };
+    (identifier, value)
+}
+
 /// A handle to a node that Servo's style traits are implemented against
 ///
 /// Since BlitzNodes are not persistent (IE we don't keep the pointers around between frames), we choose to just implement
@@ -451,7 +818,7 @@ impl<'a> TNode for BlitzNode<'a> {
     }
 
     fn opaque(&self) -> OpaqueNode {
-        OpaqueNode(self as *const _ as usize)
+        OpaqueNode(*self as *const Node as usize)
     }
 
     fn debug_id(self) -> usize {
@@ -560,30 +927,44 @@ impl<'a> selectors::Element for BlitzNode<'a> {
 
     fn attr_matches(
         &self,
-        _ns: &selectors::attr::NamespaceConstraint<
+        ns: &selectors::attr::NamespaceConstraint<
             &<Self::Impl as selectors::SelectorImpl>::NamespaceUrl,
         >,
         local_name: &<Self::Impl as selectors::SelectorImpl>::LocalName,
-        _operation: &selectors::attr::AttrSelectorOperation<
+        operation: &selectors::attr::AttrSelectorOperation<
             &<Self::Impl as selectors::SelectorImpl>::AttrValue,
         >,
     ) -> bool {
-        // println!("attr matches  {}", self.id);
-        let mut has_attr = false;
-        self.each_attr_name(|f| {
-            if f.as_ref() == local_name.as_ref() {
-                has_attr = true;
-            }
-        });
-        has_attr
+        let Some(attrs) = self.raw_dom_data.attrs() else {
+            return false;
+        };
+
+        // The operation handles the operator (e.g. `^=`) along with the selector's case-sensitivity
+        attrs.iter().any(|attr| {
+            let ns_matches = match ns {
+                NamespaceConstraint::Any => true,
+                NamespaceConstraint::Specific(ns) => attr.name.ns == ns.0,
+            };
+            ns_matches && attr.name.local == local_name.0 && operation.eval_str(&attr.value)
+        })
     }
 
     fn match_non_ts_pseudo_class(
         &self,
-        _pc: &<Self::Impl as selectors::SelectorImpl>::NonTSPseudoClass,
+        pc: &<Self::Impl as selectors::SelectorImpl>::NonTSPseudoClass,
         _context: &mut MatchingContext<Self::Impl>,
     ) -> bool {
-        false
+        match *pc {
+            NonTSPseudoClass::Link | NonTSPseudoClass::AnyLink => self.is_link(),
+            // We don't keep a history
+            NonTSPseudoClass::Visited => false,
+            NonTSPseudoClass::ReadOnly => !self.state().contains(pc.state_flag()),
+            // Everything else we support (e.g. `:hover`, `:focus`, `:disabled`) is element state
+            _ => {
+                let flag = pc.state_flag();
+                !flag.is_empty() && self.state().contains(flag)
+            }
+        }
     }
 
     fn match_pseudo_element(
@@ -711,8 +1092,7 @@ impl<'a> TElement for BlitzNode<'a> {
     }
 
     fn state(&self) -> ElementState {
-        // todo: we should track this
-        ElementState::empty()
+        self.element_state()
     }
 
     fn has_part_attr(&self) -> bool {
@@ -753,32 +1133,35 @@ impl<'a> TElement for BlitzNode<'a> {
     }
 
     fn has_dirty_descendants(&self) -> bool {
-        true
+        self.dirty_descendants.load(Ordering::Relaxed)
     }
 
     fn has_snapshot(&self) -> bool {
-        // todo: We want to implement snapshots at some point
-        false
+        self.has_snapshot.load(Ordering::Relaxed)
     }
 
     fn handled_snapshot(&self) -> bool {
-        unimplemented!()
+        self.snapshot_handled.load(Ordering::Relaxed)
     }
 
     unsafe fn set_handled_snapshot(&self) {
-        unimplemented!()
+        self.snapshot_handled.store(true, Ordering::Relaxed);
     }
 
-    unsafe fn set_dirty_descendants(&self) {}
+    unsafe fn set_dirty_descendants(&self) {
+        self.dirty_descendants.store(true, Ordering::Relaxed);
+    }
 
-    unsafe fn unset_dirty_descendants(&self) {}
+    unsafe fn unset_dirty_descendants(&self) {
+        self.dirty_descendants.store(false, Ordering::Relaxed);
+    }
 
-    fn store_children_to_process(&self, _n: isize) {
-        unimplemented!()
+    fn store_children_to_process(&self, n: isize) {
+        self.children_to_process.store(n, Ordering::Relaxed);
     }
 
     fn did_process_child(&self) -> isize {
-        unimplemented!()
+        self.children_to_process.fetch_sub(1, Ordering::Relaxed) - 1
     }
 
     unsafe fn ensure_data(&self) -> AtomicRefMut<style::data::ElementData> {
@@ -1046,3 +1429,121 @@ fn parse_inline() {
 
     // let val = CSSInlineStyleDeclaration();
 }
+
+#[test]
+fn attribute_selectors_match_operators_and_case() {
+    let doc = crate::document::Document::from_html_for_test(
+        r#"<style>
+            [data-eq="open"] { width: 1px }
+            [data-word~="open"] { width: 2px }
+            [data-lang|="en"] { width: 3px }
+            [data-pre^="http"] { width: 4px }
+            [data-suf$=".png"] { width: 5px }
+            [data-sub*="ell"] { width: 6px }
+            [data-eq="OPEN" i] { height: 1px }
+        </style>
+        <div id="eq" data-eq="open"></div>
+        <div id="eq-case" data-eq="Open"></div>
+        <div id="word" data-word="closed open"></div>
+        <div id="lang" data-lang="en-GB"></div>
+        <div id="pre" data-pre="https://example.com"></div>
+        <div id="suf" data-suf="logo.png"></div>
+        <div id="sub" data-sub="hello"></div>
+        <div id="none" data-word="opened" data-lang="english" data-pre="ftp://example.com"
+            data-suf="logo.jpg" data-sub="help"></div>"#,
+    );
+    let size = |id| doc.nodes[doc.element_by_id_for_test(id)].style.size;
+    let px = taffy::Dimension::Length;
+
+    for (id, width) in [
+        ("eq", 1.0),
+        ("word", 2.0),
+        ("lang", 3.0),
+        ("pre", 4.0),
+        ("suf", 5.0),
+        ("sub", 6.0),
+    ] {
+        assert_eq!(size(id).width, px(width), "#{id}");
+    }
+    assert_eq!(size("none").width, taffy::Dimension::Auto);
+
+    // Values are case-sensitive unless the selector has the `i` flag
+    assert_eq!(size("eq-case").width, taffy::Dimension::Auto);
+    assert_eq!(size("eq-case").height, px(1.0));
+    assert_eq!(size("eq").height, px(1.0));
+}
+
+#[test]
+fn restyles_only_changed_subtrees() {
+    use crate::node::Attribute;
+    use style::servo_arc::Arc as ServoArc;
+
+    let mut doc = crate::document::Document::from_html_for_test(
+        r#"<style>
+            .on { width: 10px }
+            .on > p { height: 5px }
+            div:hover { width: 20px }
+        </style>
+        <div id="a"><p id="a-child"></p></div>
+        <div id="b"><p id="b-child"></p></div>"#,
+    );
+    let ids = ["a", "a-child", "b", "b-child"].map(|id| doc.element_by_id_for_test(id));
+    let styles = |doc: &crate::document::Document| {
+        ids.map(|id| {
+            let data = doc.nodes[id].stylo_element_data.borrow();
+            data.as_ref().unwrap().styles.primary().clone()
+        })
+    };
+    let restyled = |before: &[ServoArc<ComputedValues>; 4], doc| {
+        before
+            .iter()
+            .zip(styles(doc))
+            .map(|(before, after)| !ServoArc::ptr_eq(before, &after))
+            .collect::<Vec<_>>()
+    };
+
+    // Adding a class restyles the element, and the children that it makes match a selector
+    let before = styles(&doc);
+    doc.snapshot_attribute_change(ids[0], "class");
+    doc.nodes[ids[0]]
+        .element_data_mut()
+        .unwrap()
+        .attrs
+        .push(Attribute {
+            name: QualName::new(None, ns!(), local_name!("class")),
+            value: "on".to_string(),
+        });
+    doc.resolve();
+    assert_eq!(restyled(&before, &doc), [true, true, false, false]);
+    assert_eq!(
+        doc.nodes[ids[1]].style.size.height,
+        taffy::Dimension::Length(5.0)
+    );
+
+    // Hovering restyles the hovered element but not its children, whose styles don't depend on it
+    let before = styles(&doc);
+    doc.set_hover_node(Some(ids[2]));
+    doc.resolve();
+    assert_eq!(restyled(&before, &doc), [false, false, true, false]);
+    assert_eq!(
+        doc.nodes[ids[2]].style.size.width,
+        taffy::Dimension::Length(20.0)
+    );
+}
+
+#[test]
+fn whitespace_text_in_flex_containers_is_shown_once_it_has_content() {
+    let mut doc = crate::document::Document::from_html_for_test(
+        r#"<div id="flex" style="display: flex"> </div>"#,
+    );
+    let flex = doc.element_by_id_for_test("flex");
+    let text = doc.nodes[flex].children[0];
+    assert_eq!(doc.nodes[text].style.display, taffy::Display::None);
+
+    if let NodeData::Text(data) = &mut doc.nodes[text].raw_dom_data {
+        data.content = "text".to_string();
+    }
+    doc.resolve();
+    assert_ne!(doc.nodes[text].style.display, taffy::Display::None);
+    assert_eq!(doc.nodes[text].display_outer, DisplayOuter::Inline);
+}
@@ -7,14 +7,20 @@ mod stylo {
     pub(crate) use style::computed_values::align_self::T as AlignSelf;
     pub(crate) use style::computed_values::flex_direction::T as FlexDirection;
     pub(crate) use style::computed_values::flex_wrap::T as FlexWrap;
+    pub(crate) use style::computed_values::grid_auto_flow::T as GridAutoFlow;
     pub(crate) use style::computed_values::justify_content::T as JustifyContent;
     // pub(crate) use style::computed_values::justify_items::T as JustifyItems;
     // pub(crate) use style::computed_values::justify_self::T as JustifySelf;
     pub(crate) use style::properties::longhands::aspect_ratio::computed_value::T as AspectRatio;
     pub(crate) use style::properties::longhands::position::computed_value::T as Position;
     pub(crate) use style::properties::style_structs::{Margin, Padding};
-    pub(crate) use style::values::computed::LengthPercentage;
+    pub(crate) use style::properties::ComputedValues;
+    pub(crate) use style::values::computed::{GridLine, GridTemplateComponent, ImplicitGridTracks};
+    pub(crate) use style::values::computed::{Length, LengthPercentage};
     pub(crate) use style::values::generics::flex::GenericFlexBasis;
+    pub(crate) use style::values::generics::grid::{
+        RepeatCount, TrackBreadth, TrackListValue, TrackSize,
+    };
     pub(crate) use style::values::generics::length::GenericLengthPercentageOrAuto;
     pub(crate) use style::values::generics::length::GenericLengthPercentageOrNormal;
     pub(crate) use style::values::generics::length::GenericMaxSize;
@@ -25,6 +31,7 @@ mod stylo {
     pub(crate) use style::values::specified::box_::DisplayInside;
     pub(crate) use style::values::specified::box_::DisplayOutside;
     pub(crate) use style::values::specified::box_::Overflow;
+    pub(crate) use style::values::specified::position::GridTemplateAreas;
     pub(crate) type LengthPercentageAuto = GenericLengthPercentageOrAuto<LengthPercentage>;
     pub(crate) type Size = GenericSize<NonNegative<LengthPercentage>>;
     pub(crate) type MaxSize = GenericMaxSize<NonNegative<LengthPercentage>>;
@@ -38,11 +45,221 @@ pub(crate) fn length_percentage(val: &stylo::LengthPercentage) -> taffy::LengthP
     } else if let Some(val) = val.to_percentage() {
         taffy::LengthPercentage::Percent(val.0)
     } else {
-        // TODO: Support calc
+        // Taffy can't represent calc() values that mix lengths and percentages. They're converted to lengths by
+        // `resolve_calc` and `resolve_container_calc` once layout knows what the percentages are relative to.
         taffy::LengthPercentage::Percent(0.0)
     }
 }
 
+fn is_calc(val: &stylo::LengthPercentage) -> bool {
+    val.to_length().is_none() && val.to_percentage().is_none()
+}
+
+/// Resolve a calc() size. Percentages of an indefinite size make it behave as auto.
+fn calc_dimension(val: &stylo::LengthPercentage, basis: Option<f32>) -> Option<taffy::Dimension> {
+    is_calc(val).then(|| match basis {
+        Some(basis) => taffy::Dimension::Length(val.resolve(stylo::Length::new(basis)).px()),
+        None => taffy::Dimension::Auto,
+    })
+}
+
+/// Resolve a calc() margin, padding or inset. Percentages of an indefinite size are treated as zero.
+fn calc_length(
+    val: &stylo::LengthPercentage,
+    basis: Option<f32>,
+) -> Option<taffy::LengthPercentage> {
+    is_calc(val).then(|| {
+        let basis = stylo::Length::new(basis.unwrap_or(0.0));
+        taffy::LengthPercentage::Length(val.resolve(basis).px())
+    })
+}
+
+fn calc_length_auto(
+    val: &stylo::LengthPercentageAuto,
+    basis: Option<f32>,
+) -> Option<taffy::LengthPercentageAuto> {
+    match val {
+        stylo::LengthPercentageAuto::LengthPercentage(val) => {
+            calc_length(val, basis).map(Into::into)
+        }
+        stylo::LengthPercentageAuto::Auto => None,
+    }
+}
+
+/// Overwrite each value that has been resolved. Returns whether there were any.
+fn apply<'a, T: 'a>(values: impl IntoIterator<Item = (&'a mut T, Option<T>)>) -> bool {
+    let mut found = false;
+    for (out, value) in values {
+        if let Some(value) = value {
+            *out = value;
+            found = true;
+        }
+    }
+    found
+}
+
+/// Overwrite the calc() values in a converted style with lengths, given the size of the containing block that their
+/// percentages are relative to and the direction of the flex container that `flex-basis` is relative to. Returns
+/// whether there were any.
+pub(crate) fn resolve_calc(
+    computed: &stylo::ComputedValues,
+    style: &mut taffy::Style,
+    basis: taffy::Size<Option<f32>>,
+    flex_direction: taffy::FlexDirection,
+) -> bool {
+    let size = |val: &stylo::Size, basis| match val {
+        stylo::Size::LengthPercentage(val) => calc_dimension(&val.0, basis),
+        stylo::Size::Auto => None,
+    };
+    let max_size = |val: &stylo::MaxSize, basis| match val {
+        stylo::MaxSize::LengthPercentage(val) => calc_dimension(&val.0, basis),
+        stylo::MaxSize::None => None,
+    };
+    let (width, height) = (basis.width, basis.height);
+
+    let position = computed.get_position();
+    let sizes = apply([
+        (&mut style.size.width, size(&position.width, width)),
+        (&mut style.size.height, size(&position.height, height)),
+        (&mut style.min_size.width, size(&position.min_width, width)),
+        (
+            &mut style.min_size.height,
+            size(&position.min_height, height),
+        ),
+        (
+            &mut style.max_size.width,
+            max_size(&position.max_width, width),
+        ),
+        (
+            &mut style.max_size.height,
+            max_size(&position.max_height, height),
+        ),
+    ]);
+
+    // Vertical margins and padding are relative to the width too
+    let margin = computed.get_margin();
+    let offsets = apply([
+        (
+            &mut style.inset.left,
+            calc_length_auto(&position.left, width),
+        ),
+        (
+            &mut style.inset.right,
+            calc_length_auto(&position.right, width),
+        ),
+        (
+            &mut style.inset.top,
+            calc_length_auto(&position.top, height),
+        ),
+        (
+            &mut style.inset.bottom,
+            calc_length_auto(&position.bottom, height),
+        ),
+        (
+            &mut style.margin.left,
+            calc_length_auto(&margin.margin_left, width),
+        ),
+        (
+            &mut style.margin.right,
+            calc_length_auto(&margin.margin_right, width),
+        ),
+        (
+            &mut style.margin.top,
+            calc_length_auto(&margin.margin_top, width),
+        ),
+        (
+            &mut style.margin.bottom,
+            calc_length_auto(&margin.margin_bottom, width),
+        ),
+    ]);
+
+    let padding = computed.get_padding();
+    let paddings = apply([
+        (
+            &mut style.padding.left,
+            calc_length(&padding.padding_left.0, width),
+        ),
+        (
+            &mut style.padding.right,
+            calc_length(&padding.padding_right.0, width),
+        ),
+        (
+            &mut style.padding.top,
+            calc_length(&padding.padding_top.0, width),
+        ),
+        (
+            &mut style.padding.bottom,
+            calc_length(&padding.padding_bottom.0, width),
+        ),
+    ]);
+
+    let main_size = match flex_direction {
+        taffy::FlexDirection::Row | taffy::FlexDirection::RowReverse => width,
+        taffy::FlexDirection::Column | taffy::FlexDirection::ColumnReverse => height,
+    };
+    let flex_basis = apply([(
+        &mut style.flex_basis,
+        match &position.flex_basis {
+            stylo::FlexBasis::Size(val) => size(val, main_size),
+            stylo::FlexBasis::Content => None,
+        },
+    )]);
+
+    sizes || offsets || paddings || flex_basis
+}
+
+/// Overwrite the calc() values in a converted style's gaps and grid tracks with lengths, given the size of the node's
+/// own content box that their percentages are relative to. Returns whether there were any.
+pub(crate) fn resolve_container_calc(
+    computed: &stylo::ComputedValues,
+    style: &mut taffy::Style,
+    content_box: taffy::Size<Option<f32>>,
+) -> bool {
+    let gap = |val: &stylo::Gap, basis| match val {
+        stylo::Gap::LengthPercentage(val) => calc_length(&val.0, basis),
+        stylo::Gap::Normal => None,
+    };
+    let (width, height) = (content_box.width, content_box.height);
+
+    let position = computed.get_position();
+    let gaps = apply([
+        (&mut style.gap.width, gap(&position.column_gap, width)),
+        (&mut style.gap.height, gap(&position.row_gap, height)),
+    ]);
+
+    let template = |val: &stylo::GridTemplateComponent, basis| {
+        template_has_calc(val).then(|| template_tracks(val, basis))
+    };
+    let auto = |val: &stylo::ImplicitGridTracks, basis| {
+        val.0
+            .iter()
+            .any(track_size_has_calc)
+            .then(|| auto_tracks(val, basis))
+    };
+    let templates = apply([
+        (
+            &mut style.grid_template_columns,
+            template(&position.grid_template_columns, width),
+        ),
+        (
+            &mut style.grid_template_rows,
+            template(&position.grid_template_rows, height),
+        ),
+    ]);
+    let autos = apply([
+        (
+            &mut style.grid_auto_columns,
+            auto(&position.grid_auto_columns, width),
+        ),
+        (
+            &mut style.grid_auto_rows,
+            auto(&position.grid_auto_rows, height),
+        ),
+    ]);
+
+    gaps || templates || autos
+}
+
 pub(crate) fn length_percentage_auto(
     val: &stylo::LengthPercentageAuto,
 ) -> taffy::LengthPercentageAuto {
@@ -105,7 +322,7 @@ pub(crate) fn display(input: stylo::Display) -> taffy::Display {
         stylo::DisplayInside::Flex => taffy::Display::Flex,
         stylo::DisplayInside::Flow => taffy::Display::Block,
         stylo::DisplayInside::FlowRoot => taffy::Display::Block,
-        // TODO: Support grid layout in servo configuration of stylo
+        stylo::DisplayInside::Grid => taffy::Display::Grid,
         // TODO: Support display:contents in Taffy
         // TODO: Support table layout in Taffy
         _ => {
@@ -135,10 +352,11 @@ pub(crate) fn position(input: stylo::Position) -> taffy::Position {
         stylo::Position::Relative => taffy::Position::Relative,
         stylo::Position::Static => taffy::Position::Relative,
 
-        // TODO: support position:fixed and sticky
         stylo::Position::Absolute => taffy::Position::Absolute,
+        // Fixed elements are laid out again against the viewport once taffy is done
         stylo::Position::Fixed => taffy::Position::Absolute,
-        stylo::Position::Sticky => taffy::Position::Absolute,
+        // Sticky elements keep their place in the flow and are offset while scrolling
+        stylo::Position::Sticky => taffy::Position::Relative,
     }
 }
 
@@ -257,3 +475,474 @@ pub(crate) fn align_self(input: stylo::AlignSelf) -> Option<taffy::AlignSelf> {
 //         stylo::JustifySelf::Baseline => Some(taffy::JustifySelf::Baseline),
 //     }
 // }
+
+pub(crate) fn grid_auto_flow(input: stylo::GridAutoFlow) -> taffy::GridAutoFlow {
+    let is_row = input.contains(stylo::GridAutoFlow::ROW);
+    let is_dense = input.contains(stylo::GridAutoFlow::DENSE);
+    match (is_row, is_dense) {
+        (true, false) => taffy::GridAutoFlow::Row,
+        (false, false) => taffy::GridAutoFlow::Column,
+        (true, true) => taffy::GridAutoFlow::RowDense,
+        (false, true) => taffy::GridAutoFlow::ColumnDense,
+    }
+}
+
+/// The numbers of a grid container's named lines along one axis. Lines are named in `grid-template-rows` or
+/// `grid-template-columns`, and each area `foo` in `grid-template-areas` lies between lines `foo-start` and `foo-end`.
+#[derive(Default)]
+pub(crate) struct GridLineNames(Vec<(String, i16)>);
+
+impl GridLineNames {
+    pub(crate) fn new(
+        template: &stylo::GridTemplateComponent,
+        areas: &stylo::GridTemplateAreas,
+        is_row: bool,
+    ) -> Self {
+        let mut names = Vec::new();
+        if let stylo::GridTemplateAreas::Areas(areas) = areas {
+            for area in areas.0.areas.iter() {
+                let lines = if is_row { &area.rows } else { &area.columns };
+                names.push((format!("{}-start", area.name), lines.start as i16));
+                names.push((format!("{}-end", area.name), lines.end as i16));
+            }
+        }
+
+        // TODO: names inside repeat()
+        if let stylo::GridTemplateComponent::TrackList(list) = template {
+            let mut line = 1;
+            for (idx, line_names) in list.line_names.iter().enumerate() {
+                names.extend(line_names.iter().map(|name| (name.0.to_string(), line)));
+                match list.values.get(idx) {
+                    Some(stylo::TrackListValue::TrackSize(_)) => line += 1,
+                    Some(stylo::TrackListValue::TrackRepeat(repeat)) => match repeat.count {
+                        stylo::RepeatCount::Number(count) => {
+                            line += (count as usize * repeat.track_sizes.len()) as i16
+                        }
+                        // How many tracks auto-fill and auto-fit repeat isn't known until layout
+                        _ => break,
+                    },
+                    None => {}
+                }
+            }
+        }
+
+        names.sort_by_key(|(_, line)| *line);
+        Self(names)
+    }
+
+    /// The lines with a name, in order
+    fn lines<'a>(&'a self, name: &'a str) -> impl Iterator<Item = i16> + 'a {
+        self.0
+            .iter()
+            .filter(move |(line_name, _)| line_name == name)
+            .map(|(_, line)| *line)
+    }
+}
+
+/// Convert an item's `grid-row` or `grid-column`, looking up named lines in its grid container
+pub(crate) fn grid_placement(
+    start: &stylo::GridLine,
+    end: &stylo::GridLine,
+    names: &GridLineNames,
+) -> taffy::Line<taffy::GridPlacement> {
+    let mut placement = taffy::Line {
+        start: grid_line(start, names, true),
+        end: grid_line(end, names, false),
+    };
+
+    // A span to a named line is counted from the other edge, so it can only be resolved once that edge is known
+    let is_named_span = |input: &stylo::GridLine| input.is_span && !input.ident.0.is_empty();
+    if let (true, taffy::GridPlacement::Line(line)) = (is_named_span(end), placement.start) {
+        placement.end = named_span(end, names, line.as_i16(), true);
+    }
+    if let (true, taffy::GridPlacement::Line(line)) = (is_named_span(start), placement.end) {
+        placement.start = named_span(start, names, line.as_i16(), false);
+    }
+    placement
+}
+
+/// Convert one edge of an item's `grid-row` or `grid-column`. `foo` on its own is the `foo-start` or `foo-end` line
+/// (whichever edge is being placed) if there is one, and otherwise the first line named `foo`.
+fn grid_line(
+    input: &stylo::GridLine,
+    names: &GridLineNames,
+    is_start: bool,
+) -> taffy::GridPlacement {
+    if input.is_auto() {
+        return taffy::GridPlacement::Auto;
+    }
+    if input.is_span {
+        return taffy::style_helpers::span(input.line_num.max(1) as u16);
+    }
+
+    let name: &str = &input.ident.0;
+    let line = match (name, input.line_num) {
+        ("", 0) => None,
+        ("", line_num) => Some(line_num as i16),
+        (name, 0) => {
+            let edge = format!("{}-{}", name, if is_start { "start" } else { "end" });
+            names
+                .lines(&edge)
+                .next()
+                .or_else(|| names.lines(name).next())
+        }
+        // The nth line with the name, counting back from the last one if n is negative
+        (name, line_num) => {
+            let lines: Vec<i16> = names.lines(name).collect();
+            let idx = if line_num > 0 {
+                Some(line_num as usize - 1)
+            } else {
+                lines.len().checked_sub(line_num.unsigned_abs() as usize)
+            };
+            idx.and_then(|idx| lines.get(idx).copied())
+        }
+    };
+    line.map_or(taffy::GridPlacement::Auto, taffy::style_helpers::line)
+}
+
+/// Resolve `span foo n`, which spans to the nth line named `foo` after (or before) the item's other edge
+fn named_span(
+    input: &stylo::GridLine,
+    names: &GridLineNames,
+    from: i16,
+    forwards: bool,
+) -> taffy::GridPlacement {
+    let name: &str = &input.ident.0;
+    let n = input.line_num.max(1) as usize;
+    let line = if forwards {
+        names.lines(name).filter(|line| *line > from).nth(n - 1)
+    } else {
+        let lines: Vec<i16> = names.lines(name).filter(|line| *line < from).collect();
+        lines.len().checked_sub(n).map(|idx| lines[idx])
+    };
+    match line {
+        Some(line) => taffy::style_helpers::span((line - from).unsigned_abs()),
+        // Without enough lines of that name the span can't reach past the explicit grid
+        None => taffy::style_helpers::span(n as u16),
+    }
+}
+
+pub(crate) fn grid_template_tracks(
+    input: &stylo::GridTemplateComponent,
+) -> Vec<taffy::TrackSizingFunction> {
+    template_tracks(input, None)
+}
+
+pub(crate) fn grid_auto_tracks(
+    input: &stylo::ImplicitGridTracks,
+) -> Vec<taffy::NonRepeatedTrackSizingFunction> {
+    auto_tracks(input, None)
+}
+
+/// Convert grid tracks, resolving calc() against the grid container's content box along the tracks' axis
+fn template_tracks(
+    input: &stylo::GridTemplateComponent,
+    basis: Option<f32>,
+) -> Vec<taffy::TrackSizingFunction> {
+    match input {
+        stylo::GridTemplateComponent::None => Vec::new(),
+        stylo::GridTemplateComponent::TrackList(list) => list
+            .values
+            .iter()
+            .map(|track| match track {
+                stylo::TrackListValue::TrackSize(size) => {
+                    taffy::TrackSizingFunction::Single(track_size(size, basis))
+                }
+                stylo::TrackListValue::TrackRepeat(repeat) => taffy::TrackSizingFunction::Repeat(
+                    track_repeat(repeat.count),
+                    repeat
+                        .track_sizes
+                        .iter()
+                        .map(|size| track_size(size, basis))
+                        .collect(),
+                ),
+            })
+            .collect(),
+        // TODO: Support subgrid and masonry in Taffy
+        stylo::GridTemplateComponent::Subgrid(_) => Vec::new(),
+        stylo::GridTemplateComponent::Masonry => Vec::new(),
+    }
+}
+
+fn auto_tracks(
+    input: &stylo::ImplicitGridTracks,
+    basis: Option<f32>,
+) -> Vec<taffy::NonRepeatedTrackSizingFunction> {
+    input.0.iter().map(|size| track_size(size, basis)).collect()
+}
+
+fn template_has_calc(input: &stylo::GridTemplateComponent) -> bool {
+    let stylo::GridTemplateComponent::TrackList(list) = input else {
+        return false;
+    };
+    list.values.iter().any(|track| match track {
+        stylo::TrackListValue::TrackSize(size) => track_size_has_calc(size),
+        stylo::TrackListValue::TrackRepeat(repeat) => {
+            repeat.track_sizes.iter().any(track_size_has_calc)
+        }
+    })
+}
+
+fn track_size_has_calc(input: &stylo::TrackSize<stylo::LengthPercentage>) -> bool {
+    let has_calc = |breadth: &stylo::TrackBreadth<stylo::LengthPercentage>| matches!(breadth, stylo::TrackBreadth::Breadth(val) if is_calc(val));
+    match input {
+        stylo::TrackSize::Breadth(breadth) | stylo::TrackSize::FitContent(breadth) => {
+            has_calc(breadth)
+        }
+        stylo::TrackSize::Minmax(min, max) => has_calc(min) || has_calc(max),
+    }
+}
+
+/// A track's length, with calc() percentages of an indefinite size treated as zero
+fn track_length(val: &stylo::LengthPercentage, basis: Option<f32>) -> taffy::LengthPercentage {
+    calc_length(val, basis).unwrap_or_else(|| length_percentage(val))
+}
+
+fn track_repeat(input: stylo::RepeatCount<i32>) -> taffy::GridTrackRepetition {
+    match input {
+        stylo::RepeatCount::Number(count) => taffy::GridTrackRepetition::Count(count as u16),
+        stylo::RepeatCount::AutoFill => taffy::GridTrackRepetition::AutoFill,
+        stylo::RepeatCount::AutoFit => taffy::GridTrackRepetition::AutoFit,
+    }
+}
+
+fn track_size(
+    input: &stylo::TrackSize<stylo::LengthPercentage>,
+    basis: Option<f32>,
+) -> taffy::NonRepeatedTrackSizingFunction {
+    match input {
+        stylo::TrackSize::Breadth(breadth) => taffy::MinMax {
+            min: min_track(breadth, basis),
+            max: max_track(breadth, basis),
+        },
+        stylo::TrackSize::Minmax(min, max) => taffy::MinMax {
+            min: min_track(min, basis),
+            max: max_track(max, basis),
+        },
+        stylo::TrackSize::FitContent(limit) => taffy::MinMax {
+            min: taffy::MinTrackSizingFunction::Auto,
+            max: match limit {
+                stylo::TrackBreadth::Breadth(limit) => {
+                    taffy::MaxTrackSizingFunction::FitContent(track_length(limit, basis))
+                }
+                // fit-content() only accepts a length or percentage
+                _ => taffy::MaxTrackSizingFunction::Auto,
+            },
+        },
+    }
+}
+
+fn min_track(
+    input: &stylo::TrackBreadth<stylo::LengthPercentage>,
+    basis: Option<f32>,
+) -> taffy::MinTrackSizingFunction {
+    match input {
+        stylo::TrackBreadth::Breadth(val) => {
+            taffy::MinTrackSizingFunction::Fixed(track_length(val, basis))
+        }
+        // Flexible sizes aren't allowed as a minimum, and behave like auto
+        stylo::TrackBreadth::Fr(_) => taffy::MinTrackSizingFunction::Auto,
+        stylo::TrackBreadth::Auto => taffy::MinTrackSizingFunction::Auto,
+        stylo::TrackBreadth::MinContent => taffy::MinTrackSizingFunction::MinContent,
+        stylo::TrackBreadth::MaxContent => taffy::MinTrackSizingFunction::MaxContent,
+    }
+}
+
+fn max_track(
+    input: &stylo::TrackBreadth<stylo::LengthPercentage>,
+    basis: Option<f32>,
+) -> taffy::MaxTrackSizingFunction {
+    match input {
+        stylo::TrackBreadth::Breadth(val) => {
+            taffy::MaxTrackSizingFunction::Fixed(track_length(val, basis))
+        }
+        stylo::TrackBreadth::Fr(val) => taffy::MaxTrackSizingFunction::Fraction(*val),
+        stylo::TrackBreadth::Auto => taffy::MaxTrackSizingFunction::Auto,
+        stylo::TrackBreadth::MinContent => taffy::MaxTrackSizingFunction::MinContent,
+        stylo::TrackBreadth::MaxContent => taffy::MaxTrackSizingFunction::MaxContent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+    use style::values::computed::Percentage;
+    use style::values::generics::grid::{TrackList, TrackRepeat};
+    use style::values::CustomIdent;
+    use style::Atom;
+    use taffy::style_helpers::{line, span};
+
+    fn named_line(name: &str, line_num: i32, is_span: bool) -> stylo::GridLine {
+        stylo::GridLine {
+            ident: CustomIdent(Atom::from(name)),
+            line_num,
+            is_span,
+        }
+    }
+
+    fn line_names(names: &[(&str, i16)]) -> GridLineNames {
+        GridLineNames(
+            names
+                .iter()
+                .map(|(name, line)| (name.to_string(), *line))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn grid_lines_resolve_names() {
+        let names = line_names(&[
+            ("col", 1),
+            ("a-start", 2),
+            ("col", 2),
+            ("a-end", 3),
+            ("col", 4),
+        ]);
+        let place = |start, end| grid_placement(&start, &end, &names);
+
+        // An area's name places each edge on its own side of the area
+        assert_eq!(
+            place(named_line("a", 0, false), named_line("a", 0, false)),
+            taffy::Line {
+                start: line(2),
+                end: line(3)
+            }
+        );
+        // Otherwise names count through the lines with that name, from the end if negative
+        assert_eq!(
+            place(named_line("col", 2, false), named_line("col", -1, false)),
+            taffy::Line {
+                start: line(2),
+                end: line(4)
+            }
+        );
+        assert_eq!(
+            place(named_line("col", 5, false), named_line("missing", 0, false)).start,
+            taffy::GridPlacement::Auto
+        );
+        // Named spans reach from the other edge to the nth line with the name
+        assert_eq!(
+            place(named_line("", 1, false), named_line("col", 2, true)),
+            taffy::Line {
+                start: line(1),
+                end: span(3)
+            }
+        );
+        assert_eq!(
+            place(named_line("col", 1, true), named_line("", 4, false)),
+            taffy::Line {
+                start: span(2),
+                end: line(4)
+            }
+        );
+    }
+
+    #[test]
+    fn grid_template_tracks_and_line_names() {
+        // [a] 10px repeat(2, 1fr) [b] 50%
+        let px = |px| {
+            stylo::TrackBreadth::Breadth(stylo::LengthPercentage::new_length(stylo::Length::new(
+                px,
+            )))
+        };
+        let template = stylo::GridTemplateComponent::TrackList(Box::new(TrackList {
+            auto_repeat_index: usize::MAX,
+            values: vec![
+                stylo::TrackListValue::TrackSize(stylo::TrackSize::Breadth(px(10.0))),
+                stylo::TrackListValue::TrackRepeat(TrackRepeat {
+                    count: stylo::RepeatCount::Number(2),
+                    line_names: vec![Default::default(); 2].into(),
+                    track_sizes: vec![stylo::TrackSize::Breadth(stylo::TrackBreadth::Fr(1.0))]
+                        .into(),
+                }),
+                stylo::TrackListValue::TrackSize(stylo::TrackSize::Breadth(
+                    stylo::TrackBreadth::Breadth(stylo::LengthPercentage::new_percent(Percentage(
+                        0.5,
+                    ))),
+                )),
+            ]
+            .into(),
+            line_names: vec![
+                vec![CustomIdent(Atom::from("a"))].into(),
+                Default::default(),
+                vec![CustomIdent(Atom::from("b"))].into(),
+                Default::default(),
+            ]
+            .into(),
+        }));
+
+        let fixed = |val| taffy::MinMax {
+            min: taffy::MinTrackSizingFunction::Fixed(val),
+            max: taffy::MaxTrackSizingFunction::Fixed(val),
+        };
+        assert_eq!(
+            grid_template_tracks(&template),
+            vec![
+                taffy::TrackSizingFunction::Single(fixed(taffy::LengthPercentage::Length(10.0))),
+                taffy::TrackSizingFunction::Repeat(
+                    taffy::GridTrackRepetition::Count(2),
+                    vec![taffy::MinMax {
+                        min: taffy::MinTrackSizingFunction::Auto,
+                        max: taffy::MaxTrackSizingFunction::Fraction(1.0),
+                    }],
+                ),
+                taffy::TrackSizingFunction::Single(fixed(taffy::LengthPercentage::Percent(0.5))),
+            ]
+        );
+
+        // The repeat() adds two lines before `b`
+        let names = GridLineNames::new(&template, &stylo::GridTemplateAreas::None, false);
+        assert_eq!(names.0, [("a".to_string(), 1), ("b".to_string(), 4)]);
+    }
+
+    /// The converted style of the element with an id, once the document has been laid out
+    fn resolved_style(html: &str, id: &str) -> taffy::Style {
+        let doc = Document::from_html_for_test(html);
+        doc.nodes[doc.element_by_id_for_test(id)].style.clone()
+    }
+
+    #[test]
+    fn calc_resolves_against_definite_containing_block() {
+        let style = resolved_style(
+            r#"<div style="display: flex; width: 200px">
+                <div id="item" style="width: calc(50% + 10px); flex-basis: calc(25% + 5px); margin-left: calc(10% + 1px)"></div>
+            </div>"#,
+            "item",
+        );
+        assert_eq!(style.size.width, taffy::Dimension::Length(110.0));
+        assert_eq!(style.flex_basis, taffy::Dimension::Length(55.0));
+        assert_eq!(style.margin.left, taffy::LengthPercentageAuto::Length(21.0));
+    }
+
+    #[test]
+    fn calc_against_indefinite_containing_block_is_auto() {
+        // The flex container's height (its main size) depends on its content
+        let style = resolved_style(
+            r#"<div style="display: flex; flex-direction: column">
+                <div id="item" style="height: calc(50% + 10px); flex-basis: calc(50% + 10px)"></div>
+            </div>"#,
+            "item",
+        );
+        assert_eq!(style.size.height, taffy::Dimension::Auto);
+        assert_eq!(style.flex_basis, taffy::Dimension::Auto);
+    }
+
+    #[test]
+    fn calc_gaps_and_tracks_resolve_against_container() {
+        let style = resolved_style(
+            r#"<div id="grid" style="display: grid; width: 200px; grid-template-columns: calc(50% + 10px) 1fr; column-gap: calc(10% + 2px)">
+                <div></div>
+            </div>"#,
+            "grid",
+        );
+        assert_eq!(style.gap.width, taffy::LengthPercentage::Length(22.0));
+        assert_eq!(
+            style.grid_template_columns[0],
+            taffy::TrackSizingFunction::Single(taffy::MinMax {
+                min: taffy::MinTrackSizingFunction::Fixed(taffy::LengthPercentage::Length(110.0)),
+                max: taffy::MaxTrackSizingFunction::Fixed(taffy::LengthPercentage::Length(110.0)),
+            })
+        );
+    }
+}
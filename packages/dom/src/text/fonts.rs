@@ -0,0 +1,664 @@
+//! Font matching, fallback and shaping
+//!
+//! Each element's `font-family`, `font-weight`, `font-stretch` and `font-style` are matched against the fonts loaded by
+//! `@font-face` rules and the installed fonts (plus the bundled Roboto) to pick its primary font. Text is shaped with swash, so kerning, ligatures and complex
+//! scripts come from the font's OpenType tables. Any characters that the primary font doesn't have glyphs for are
+//! split out into runs drawn with a fallback font, which is looked for among the installed fonts the first time it's
+//! needed.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use style::font_face::{FontFaceRuleData, FontStyle as FontFaceStyle};
+use style::properties::ComputedValues;
+use style::values::computed::font::{FontStyle, GenericFontFamily, SingleFontFamily};
+use swash::shape::{Direction, ShapeContext};
+use swash::text::{Codepoint, Script};
+use swash::{CacheKey, FontRef, Metrics};
+
+use super::{PositionedGlyph, TextStyle, DEFAULT_FONT};
+
+/// Index of a font registered with a [`FontContext`]
+pub type FontId = usize;
+
+/// The bundled font, which text is drawn with when none of its font families are installed
+pub const DEFAULT_FONT_ID: FontId = 0;
+
+/// The family name of the bundled font
+const BUNDLED_FAMILY: &str = "Roboto";
+
+/// Families tried (in order) for characters missing from the primary font, before searching every installed font
+const FALLBACK_FAMILIES: &[&str] = &[
+    "Noto Sans",
+    "DejaVu Sans",
+    "Noto Sans CJK SC",
+    "Noto Sans Arabic",
+    "Noto Sans Hebrew",
+    "Noto Sans Devanagari",
+    "Noto Color Emoji",
+    "Segoe UI",
+    "Microsoft YaHei",
+    "Segoe UI Emoji",
+    "Helvetica Neue",
+    "PingFang SC",
+    "Apple Color Emoji",
+    "Arial Unicode MS",
+];
+
+/// Installed families used for the generic font families, in order of preference. If none of them are installed the
+/// generic family falls back to the bundled font.
+const GENERIC_FAMILIES: &[(fontdb::Family, &[&str])] = &[
+    (
+        fontdb::Family::Serif,
+        &[
+            "Times New Roman",
+            "Times",
+            "Noto Serif",
+            "DejaVu Serif",
+            "Liberation Serif",
+        ],
+    ),
+    (
+        fontdb::Family::SansSerif,
+        &[
+            "Helvetica",
+            "Arial",
+            "Noto Sans",
+            "DejaVu Sans",
+            "Liberation Sans",
+        ],
+    ),
+    (
+        fontdb::Family::Monospace,
+        &[
+            "Menlo",
+            "Consolas",
+            "Courier New",
+            "Noto Sans Mono",
+            "DejaVu Sans Mono",
+            "Liberation Mono",
+        ],
+    ),
+    (
+        fontdb::Family::Cursive,
+        &["Apple Chancery", "Comic Sans MS", "URW Chancery L"],
+    ),
+    (fontdb::Family::Fantasy, &["Papyrus", "Impact"]),
+];
+
+#[derive(Clone)]
+pub struct FontData {
+    pub data: Arc<Vec<u8>>,
+    /// Index of the font within a font collection
+    pub index: u32,
+}
+
+struct LoadedFont {
+    data: FontData,
+    offset: u32,
+    /// Kept so that swash's shaping caches are reused between runs
+    key: CacheKey,
+}
+
+impl LoadedFont {
+    fn new(data: FontData) -> Option<Self> {
+        let font = FontRef::from_index(&data.data, data.index as usize)?;
+        let (offset, key) = (font.offset, font.key);
+        Some(Self { data, offset, key })
+    }
+
+    fn as_ref(&self) -> FontRef<'_> {
+        FontRef {
+            data: &self.data.data,
+            offset: self.offset,
+            key: self.key,
+        }
+    }
+
+    fn covers(&self, ch: char) -> bool {
+        self.as_ref().charmap().map(ch) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum FontFamily {
+    Named(String),
+    Generic(fontdb::Family<'static>),
+}
+
+/// The computed font properties that select which font an element's text is drawn with
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct FontDescriptor {
+    families: Vec<FontFamily>,
+    weight: fontdb::Weight,
+    stretch: fontdb::Stretch,
+    style: fontdb::Style,
+}
+
+impl FontDescriptor {
+    pub(crate) fn from_computed(style: &ComputedValues) -> Self {
+        let font = style.get_font();
+
+        let families = font
+            .font_family
+            .families
+            .iter()
+            .filter_map(|family| match family {
+                SingleFontFamily::FamilyName(name) => {
+                    Some(FontFamily::Named(name.name.to_string()))
+                }
+                SingleFontFamily::Generic(generic) => {
+                    let generic = match generic {
+                        GenericFontFamily::Serif => fontdb::Family::Serif,
+                        GenericFontFamily::SansSerif | GenericFontFamily::SystemUi => {
+                            fontdb::Family::SansSerif
+                        }
+                        GenericFontFamily::Monospace => fontdb::Family::Monospace,
+                        GenericFontFamily::Cursive => fontdb::Family::Cursive,
+                        GenericFontFamily::Fantasy => fontdb::Family::Fantasy,
+                        _ => return None,
+                    };
+                    Some(FontFamily::Generic(generic))
+                }
+            })
+            .collect();
+
+        let weight = fontdb::Weight(font.clone_font_weight().value().round() as u16);
+        let stretch = stretch_from_percentage(font.clone_font_stretch().to_percentage().0 * 100.0);
+
+        let style = match font.clone_font_style() {
+            font_style if font_style == FontStyle::NORMAL => fontdb::Style::Normal,
+            font_style if font_style == FontStyle::ITALIC => fontdb::Style::Italic,
+            _ => fontdb::Style::Oblique,
+        };
+
+        Self {
+            families,
+            weight,
+            stretch,
+            style,
+        }
+    }
+}
+
+/// Font files only describe their width with the nine keyword values, so snap to the nearest one
+fn stretch_from_percentage(stretch: f32) -> fontdb::Stretch {
+    match stretch {
+        s if s <= 56.25 => fontdb::Stretch::UltraCondensed,
+        s if s <= 68.75 => fontdb::Stretch::ExtraCondensed,
+        s if s <= 81.25 => fontdb::Stretch::Condensed,
+        s if s <= 93.75 => fontdb::Stretch::SemiCondensed,
+        s if s <= 106.25 => fontdb::Stretch::Normal,
+        s if s <= 118.75 => fontdb::Stretch::SemiExpanded,
+        s if s <= 137.5 => fontdb::Stretch::Expanded,
+        s if s <= 175.0 => fontdb::Stretch::ExtraExpanded,
+        _ => fontdb::Stretch::UltraExpanded,
+    }
+}
+
+/// The descriptors of an `@font-face` rule that decide which text its font is used for. Those that the rule leaves
+/// out are read from the font itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontFaceDescriptors {
+    pub family: String,
+    /// The range of weights the face covers, which is a single weight unless it's a variable font
+    pub weight: Option<(fontdb::Weight, fontdb::Weight)>,
+    pub stretch: Option<(fontdb::Stretch, fontdb::Stretch)>,
+    pub style: Option<fontdb::Style>,
+}
+
+impl FontFaceDescriptors {
+    pub(crate) fn from_rule(font_face: &FontFaceRuleData) -> Option<Self> {
+        let weight = |weight: f32| fontdb::Weight(weight.round() as u16);
+        let stretch = |stretch: f32| stretch_from_percentage(stretch * 100.0);
+        Some(Self {
+            family: font_face.family.as_ref()?.name.to_string(),
+            weight: font_face.weight.as_ref().map(|range| {
+                (
+                    weight(range.0.compute().value()),
+                    weight(range.1.compute().value()),
+                )
+            }),
+            stretch: font_face.stretch.as_ref().map(|range| {
+                (
+                    stretch(range.0.compute().to_percentage().0),
+                    stretch(range.1.compute().to_percentage().0),
+                )
+            }),
+            style: font_face.style.as_ref().map(|style| match style {
+                FontFaceStyle::Normal => fontdb::Style::Normal,
+                FontFaceStyle::Italic => fontdb::Style::Italic,
+                FontFaceStyle::Oblique(..) => fontdb::Style::Oblique,
+            }),
+        })
+    }
+}
+
+/// A face loaded by an `@font-face` rule, which is only used for the rule's font-family
+struct WebFont {
+    family: String,
+    weight: (fontdb::Weight, fontdb::Weight),
+    stretch: (fontdb::Stretch, fontdb::Stretch),
+    style: fontdb::Style,
+    font_id: FontId,
+}
+
+/// The fonts used by a document, shared between layout (which shapes text to measure it) and the renderer (which
+/// draws the shaped glyphs)
+pub struct FontContext {
+    inner: Mutex<FontContextInner>,
+}
+
+struct FontContextInner {
+    fonts: Vec<LoadedFont>,
+    /// Installed fonts (and the bundled font), which are only enumerated once we need to match a font-family or find
+    /// a fallback
+    system_fonts: Option<fontdb::Database>,
+    system_font_ids: HashMap<fontdb::ID, FontId>,
+    /// Faces loaded by `@font-face` rules
+    web_fonts: Vec<WebFont>,
+    /// The primary font matched for each set of font properties
+    matches: HashMap<FontDescriptor, FontId>,
+    /// The fallback font chosen for each character, or `None` if no installed font covers it
+    fallbacks: HashMap<char, Option<FontId>>,
+    shape_context: ShapeContext,
+}
+
+impl Default for FontContext {
+    fn default() -> Self {
+        let default_font = LoadedFont::new(FontData {
+            data: Arc::new(DEFAULT_FONT.to_vec()),
+            index: 0,
+        })
+        .unwrap();
+
+        Self {
+            inner: Mutex::new(FontContextInner {
+                fonts: vec![default_font],
+                system_fonts: None,
+                system_font_ids: HashMap::new(),
+                web_fonts: Vec::new(),
+                matches: HashMap::new(),
+                fallbacks: HashMap::new(),
+                shape_context: ShapeContext::new(),
+            }),
+        }
+    }
+}
+
+impl FontContext {
+    pub fn font_data(&self, font_id: FontId) -> FontData {
+        self.inner.lock().unwrap().fonts[font_id].data.clone()
+    }
+
+    /// The font's vertical metrics, scaled to `font_size`
+    pub fn metrics(&self, font_id: FontId, font_size: f32) -> Metrics {
+        self.inner.lock().unwrap().fonts[font_id]
+            .as_ref()
+            .metrics(&[])
+            .scale(font_size)
+    }
+
+    /// Pick the primary font for text with these font properties. Families are tried in order and the closest
+    /// weight, stretch and style within the first installed family wins, as in CSS font matching.
+    pub(crate) fn resolve(&self, descriptor: &FontDescriptor) -> FontId {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(font_id) = inner.matches.get(descriptor) {
+            return *font_id;
+        }
+
+        let font_id = inner.find_match(descriptor).unwrap_or(DEFAULT_FONT_ID);
+        inner.matches.insert(descriptor.clone(), font_id);
+        font_id
+    }
+
+    /// Register a font loaded by an `@font-face` rule, so that text with the rule's font-family (and a weight, width
+    /// and style close to the rule's) is drawn with it. Returns `false` if the data isn't a font we can read.
+    pub fn register_web_font(&self, descriptors: &FontFaceDescriptors, data: Vec<u8>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let data = Arc::new(data);
+
+        // Only used to read the weight, width and style of each face in the font
+        let mut db = fontdb::Database::new();
+        let face_ids = db.load_font_source(fontdb::Source::Binary(data.clone()));
+
+        let mut registered = false;
+        for face_id in face_ids {
+            let Some(face) = db.face(face_id) else {
+                continue;
+            };
+            let Some(font) = LoadedFont::new(FontData {
+                data: data.clone(),
+                index: face.index,
+            }) else {
+                continue;
+            };
+
+            inner.fonts.push(font);
+            let font_id = inner.fonts.len() - 1;
+            inner.web_fonts.push(WebFont {
+                family: descriptors.family.clone(),
+                weight: descriptors.weight.unwrap_or((face.weight, face.weight)),
+                stretch: descriptors.stretch.unwrap_or((face.stretch, face.stretch)),
+                style: descriptors.style.unwrap_or(face.style),
+                font_id,
+            });
+            registered = true;
+        }
+
+        // Text that was matched to another font before this one loaded may want this one now
+        if registered {
+            inner.matches.clear();
+            inner.fallbacks.clear();
+        }
+        registered
+    }
+
+    /// Shape a run of text that won't be broken across lines, returning its glyphs along with its width
+    pub fn shape(&self, text: &str, style: &TextStyle) -> (Vec<PositionedGlyph>, f32) {
+        let mut inner = self.inner.lock().unwrap();
+
+        let mut glyphs = Vec::new();
+        let mut width = 0.0;
+        for (range, font_id) in inner.itemize(text, style.font) {
+            width += inner.shape_run(&text[range], font_id, style, width, &mut glyphs);
+        }
+
+        (glyphs, width)
+    }
+}
+
+impl FontContextInner {
+    fn system_fonts(&mut self) -> &fontdb::Database {
+        if self.system_fonts.is_none() {
+            let mut db = fontdb::Database::new();
+            db.load_system_fonts();
+
+            // The bundled font can be asked for by name, and is what the generic families fall back to
+            let bundled = fontdb::Source::Binary(self.fonts[DEFAULT_FONT_ID].data.data.clone());
+            for face_id in db.load_font_source(bundled) {
+                self.system_font_ids.insert(face_id, DEFAULT_FONT_ID);
+            }
+
+            for (generic, candidates) in GENERIC_FAMILIES {
+                let installed = candidates.iter().find(|name| {
+                    db.faces()
+                        .any(|face| face.families.iter().any(|(family, _)| family == **name))
+                });
+                let family = installed.copied().unwrap_or(BUNDLED_FAMILY);
+                match generic {
+                    fontdb::Family::Serif => db.set_serif_family(family),
+                    fontdb::Family::SansSerif => db.set_sans_serif_family(family),
+                    fontdb::Family::Monospace => db.set_monospace_family(family),
+                    fontdb::Family::Cursive => db.set_cursive_family(family),
+                    fontdb::Family::Fantasy => db.set_fantasy_family(family),
+                    fontdb::Family::Name(_) => {}
+                }
+            }
+
+            self.system_fonts = Some(db);
+        }
+        self.system_fonts.as_ref().unwrap()
+    }
+
+    fn find_match(&mut self, descriptor: &FontDescriptor) -> Option<FontId> {
+        for family in &descriptor.families {
+            // Fonts from @font-face rules take precedence over installed fonts with the same name
+            if let FontFamily::Named(name) = family {
+                if let Some(font_id) = self.find_web_font(name, descriptor) {
+                    return Some(font_id);
+                }
+            }
+
+            let family = match family {
+                FontFamily::Named(name) => fontdb::Family::Name(name),
+                FontFamily::Generic(generic) => *generic,
+            };
+            let query = fontdb::Query {
+                families: &[family],
+                weight: descriptor.weight,
+                stretch: descriptor.stretch,
+                style: descriptor.style,
+            };
+            if let Some(face_id) = self.system_fonts().query(&query) {
+                return self.load_system_font(face_id);
+            }
+        }
+
+        None
+    }
+
+    /// Pick the web font face closest to the descriptor, preferring the right style, then width, then weight
+    fn find_web_font(&self, family: &str, descriptor: &FontDescriptor) -> Option<FontId> {
+        self.web_fonts
+            .iter()
+            .filter(|font| font.family.eq_ignore_ascii_case(family))
+            .min_by_key(|font| {
+                let style_distance = match (font.style, descriptor.style) {
+                    (style, wanted) if style == wanted => 0,
+                    (fontdb::Style::Normal, _) | (_, fontdb::Style::Normal) => 2,
+                    // Italic and oblique can stand in for each other
+                    _ => 1,
+                };
+                // Faces that cover a range of weights or widths are a perfect match anywhere within it
+                let distance = |min: i32, max: i32, wanted: i32| {
+                    (min.min(max) - wanted).max(wanted - min.max(max)).max(0)
+                };
+                let (min_stretch, max_stretch) = font.stretch;
+                let stretch_distance = distance(
+                    min_stretch.to_number() as i32,
+                    max_stretch.to_number() as i32,
+                    descriptor.stretch.to_number() as i32,
+                );
+                let (min_weight, max_weight) = font.weight;
+                let weight_distance = distance(
+                    min_weight.0 as i32,
+                    max_weight.0 as i32,
+                    descriptor.weight.0 as i32,
+                );
+                (style_distance, stretch_distance, weight_distance)
+            })
+            .map(|font| font.font_id)
+    }
+
+    /// Split text into runs that can each be drawn with a single font
+    fn itemize(&mut self, text: &str, primary: FontId) -> Vec<(Range<usize>, FontId)> {
+        let mut runs: Vec<(Range<usize>, FontId)> = Vec::new();
+        for (idx, ch) in text.char_indices() {
+            let end = idx + ch.len_utf8();
+
+            // Combining marks and joiners belong with the character before them
+            if let Some((range, _)) = runs.last_mut() {
+                if ch.script() == Script::Inherited || ch == '\u{200D}' {
+                    range.end = end;
+                    continue;
+                }
+            }
+
+            let font_id = self.font_for_char(ch, primary);
+            match runs.last_mut() {
+                Some((range, run_font_id)) if *run_font_id == font_id => range.end = end,
+                _ => runs.push((idx..end, font_id)),
+            }
+        }
+        runs
+    }
+
+    fn font_for_char(&mut self, ch: char, primary: FontId) -> FontId {
+        if ch.is_whitespace() || self.fonts[primary].covers(ch) {
+            return primary;
+        }
+
+        let fallback = match self.fallbacks.get(&ch) {
+            Some(fallback) => *fallback,
+            None => {
+                let fallback = self.find_fallback(ch);
+                self.fallbacks.insert(ch, fallback);
+                fallback
+            }
+        };
+
+        // With no font that covers the character we may as well draw the primary font's notdef glyph
+        fallback.unwrap_or(primary)
+    }
+
+    fn find_fallback(&mut self, ch: char) -> Option<FontId> {
+        // Fonts we've already loaded are much cheaper to check than installed ones
+        if let Some(font_id) = self.fonts.iter().position(|font| font.covers(ch)) {
+            return Some(font_id);
+        }
+
+        let db = self.system_fonts();
+        let preferred_faces = FALLBACK_FAMILIES.iter().flat_map(|family| {
+            db.faces()
+                .filter(move |face| face.families.iter().any(|(name, _)| name == family))
+        });
+        let face_id = preferred_faces
+            .chain(db.faces())
+            .map(|face| face.id)
+            .find(|&face_id| {
+                db.with_face_data(face_id, |data, index| {
+                    FontRef::from_index(data, index as usize)
+                        .is_some_and(|font| font.charmap().map(ch) != 0)
+                })
+                .unwrap_or(false)
+            })?;
+
+        self.load_system_font(face_id)
+    }
+
+    fn load_system_font(&mut self, face_id: fontdb::ID) -> Option<FontId> {
+        if let Some(font_id) = self.system_font_ids.get(&face_id) {
+            return Some(*font_id);
+        }
+
+        let data = self
+            .system_fonts
+            .as_ref()?
+            .with_face_data(face_id, |data, index| FontData {
+                data: Arc::new(data.to_vec()),
+                index,
+            })?;
+        self.fonts.push(LoadedFont::new(data)?);
+
+        let font_id = self.fonts.len() - 1;
+        self.system_font_ids.insert(face_id, font_id);
+        Some(font_id)
+    }
+
+    /// Shape text with a single font, appending its glyphs (starting at `offset`) and returning its width
+    fn shape_run(
+        &mut self,
+        text: &str,
+        font_id: FontId,
+        style: &TextStyle,
+        offset: f32,
+        glyphs: &mut Vec<PositionedGlyph>,
+    ) -> f32 {
+        let script = text
+            .chars()
+            .map(|ch| ch.script())
+            .find(|script| !matches!(script, Script::Common | Script::Inherited | Script::Unknown))
+            .unwrap_or(Script::Latin);
+        let direction = if is_rtl(script) {
+            Direction::RightToLeft
+        } else {
+            Direction::LeftToRight
+        };
+
+        // Letter-spacing would pull ligatures apart, so turn them off
+        let features: &[(&str, u16)] = if style.letter_spacing != 0.0 {
+            &[("liga", 0), ("clig", 0)]
+        } else {
+            &[]
+        };
+
+        let mut shaper = self
+            .shape_context
+            .builder(self.fonts[font_id].as_ref())
+            .script(script)
+            .direction(direction)
+            .size(style.font_size)
+            .features(features)
+            .build();
+        shaper.add_str(text);
+
+        let mut clusters = Vec::new();
+        shaper.shape_with(|cluster| clusters.push(cluster.glyphs.to_vec()));
+
+        // Clusters come out in logical order, but we lay glyphs out left to right. Words of different directions are
+        // put in order when lines are laid out.
+        // TODO: bidi reordering of mixed direction text within a word
+        if direction == Direction::RightToLeft {
+            clusters.reverse();
+        }
+
+        let mut x = offset;
+        for cluster in clusters {
+            for glyph in cluster {
+                glyphs.push(PositionedGlyph {
+                    id: glyph.id,
+                    font: font_id,
+                    x: x + glyph.x,
+                    y: -glyph.y,
+                });
+                x += glyph.advance;
+            }
+            x += style.letter_spacing;
+        }
+
+        x - offset
+    }
+}
+
+fn is_rtl(script: Script) -> bool {
+    matches!(
+        script,
+        Script::Arabic
+            | Script::Hebrew
+            | Script::Syriac
+            | Script::Thaana
+            | Script::Nko
+            | Script::Adlam
+            | Script::Mandaic
+            | Script::Samaritan
+            | Script::HanifiRohingya
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_fonts_are_matched_by_their_descriptors() {
+        let fonts = FontContext::default();
+        let face = |weight, style| FontFaceDescriptors {
+            family: "Web".to_string(),
+            weight: Some((fontdb::Weight(weight), fontdb::Weight(weight))),
+            stretch: None,
+            style: Some(style),
+        };
+        // The same regular font stands in for each face, so only the descriptors tell them apart
+        assert!(fonts.register_web_font(&face(400, fontdb::Style::Normal), DEFAULT_FONT.to_vec()));
+        assert!(fonts.register_web_font(&face(700, fontdb::Style::Normal), DEFAULT_FONT.to_vec()));
+        assert!(fonts.register_web_font(&face(400, fontdb::Style::Italic), DEFAULT_FONT.to_vec()));
+
+        let resolve = |weight, style| {
+            fonts.resolve(&FontDescriptor {
+                families: vec![FontFamily::Named("Web".to_string())],
+                weight: fontdb::Weight(weight),
+                stretch: fontdb::Stretch::Normal,
+                style,
+            })
+        };
+        let regular = resolve(400, fontdb::Style::Normal);
+        let bold = resolve(800, fontdb::Style::Normal);
+        let italic = resolve(400, fontdb::Style::Italic);
+        assert_ne!(regular, DEFAULT_FONT_ID);
+        assert_ne!(bold, regular);
+        assert_ne!(italic, regular);
+        assert_ne!(italic, bold);
+    }
+}
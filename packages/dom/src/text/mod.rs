@@ -0,0 +1,544 @@
+//! Measuring and laying out text
+//!
+//! Layout positions every glyph up front and stores the result on each text node as a [`TextLayout`], which the
+//! renderer draws as-is, so what gets painted always matches what was measured. Glyphs are shaped (and fall back to
+//! installed fonts) by the document's [`FontContext`].
+
+mod fonts;
+mod woff;
+
+pub(crate) use fonts::FontDescriptor;
+pub use fonts::{FontContext, FontData, FontFaceDescriptors, FontId, DEFAULT_FONT_ID};
+pub(crate) use woff::decode_font;
+
+use style::computed_values::direction::T as ComputedDirection;
+use style::computed_values::white_space::T as ComputedWhiteSpace;
+use style::properties::ComputedValues;
+use style::values::computed::{Length, LineHeight};
+use unicode_bidi::{BidiInfo, Level};
+use unicode_linebreak::BreakOpportunity;
+
+pub const LOREM_IPSUM : &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum.";
+
+/// The bundled font, used when none of an element's font families are installed
+pub const DEFAULT_FONT: &[u8] = include_bytes!("Roboto-Regular.ttf");
+
+/// Slack allowed when deciding whether a word fits on a line, so that re-laying out text at exactly its measured width
+/// doesn't wrap because of float rounding
+pub(crate) const LINE_FIT_EPSILON: f32 = 0.01;
+
+/// How many spaces wide a preserved tab is
+const TAB_SIZE: usize = 8;
+
+/// The computed styles that affect how text is measured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    /// The primary font matched from the font-family, font-weight, font-stretch and font-style properties
+    pub font: FontId,
+    pub font_size: f32,
+    /// `None` for `line-height: normal`, which is resolved from the font's metrics
+    pub line_height: Option<f32>,
+    pub letter_spacing: f32,
+    pub white_space: WhiteSpace,
+    /// Whether the base direction of paragraphs, from the `direction` property, is right-to-left
+    pub rtl: bool,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            font: DEFAULT_FONT_ID,
+            font_size: 16.0,
+            line_height: None,
+            letter_spacing: 0.0,
+            white_space: WhiteSpace::Normal,
+            rtl: false,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn from_computed(style: &ComputedValues, fonts: &FontContext) -> Self {
+        let font = style.get_font();
+        let font_size = font.font_size.computed_size().px();
+
+        let line_height = match font.clone_line_height() {
+            LineHeight::Normal => None,
+            LineHeight::Number(number) => Some(font_size * number.0),
+            LineHeight::Length(length) => Some(length.0.px()),
+        };
+
+        // Percentages are relative to the font size
+        let letter_spacing = style
+            .get_inherited_text()
+            .clone_letter_spacing()
+            .0
+            .resolve(Length::new(font_size))
+            .px();
+
+        let white_space = match style.get_inherited_text().clone_white_space() {
+            ComputedWhiteSpace::Normal => WhiteSpace::Normal,
+            ComputedWhiteSpace::Pre => WhiteSpace::Pre,
+            ComputedWhiteSpace::Nowrap => WhiteSpace::Nowrap,
+            ComputedWhiteSpace::PreWrap => WhiteSpace::PreWrap,
+            ComputedWhiteSpace::PreLine => WhiteSpace::PreLine,
+        };
+
+        let rtl = style.get_inherited_box().clone_direction() == ComputedDirection::Rtl;
+
+        Self {
+            font: fonts.resolve(&FontDescriptor::from_computed(style)),
+            font_size,
+            line_height,
+            letter_spacing,
+            white_space,
+            rtl,
+        }
+    }
+
+    pub fn line_metrics(&self, fonts: &FontContext) -> LineMetrics {
+        let metrics = fonts.metrics(self.font, self.font_size);
+        let content_height = metrics.ascent + metrics.descent;
+        let line_height = self.line_height.unwrap_or(content_height + metrics.leading);
+
+        LineMetrics {
+            line_height,
+            baseline: (line_height - content_height) / 2.0 + metrics.ascent,
+        }
+    }
+
+    /// The width of a collapsed run of whitespace
+    pub fn space_width(&self, fonts: &FontContext) -> f32 {
+        layout_word(fonts, " ", self).1
+    }
+}
+
+/// Which whitespace is collapsed and whether lines can wrap, from the `white-space` property
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+}
+
+impl WhiteSpace {
+    /// Whether runs of spaces and tabs collapse into a single space
+    pub fn collapses_spaces(self) -> bool {
+        matches!(self, Self::Normal | Self::Nowrap | Self::PreLine)
+    }
+
+    /// Whether newlines force line breaks, rather than collapsing like any other space
+    pub fn preserves_newlines(self) -> bool {
+        matches!(self, Self::Pre | Self::PreWrap | Self::PreLine)
+    }
+
+    /// Whether lines can be broken to fit the available width
+    pub fn wraps(self) -> bool {
+        matches!(self, Self::Normal | Self::PreWrap | Self::PreLine)
+    }
+}
+
+/// A piece of text, split wherever whitespace is collapsed, preserved or forces a line break
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum TextSegment<'a> {
+    Word(&'a str),
+    /// A place between two parts of a word where lines can be broken, like between CJK characters or after a hyphen
+    WordBreak,
+    /// A run of whitespace that collapses into a single space, or into nothing at the start and end of lines
+    CollapsibleSpace,
+    /// Whitespace that is kept as written. Lines can break after it if the text wraps.
+    PreservedSpace(&'a str),
+    /// A preserved newline (or other mandatory break), which ends the line
+    Newline,
+}
+
+/// Split text into words and whitespace according to `white-space`, along with the byte offset of each segment.
+/// Words are split further wherever the Unicode line breaking algorithm (UAX #14) allows a break. Non-breaking spaces
+/// are part of words.
+pub(crate) fn segment_text(text: &str, white_space: WhiteSpace) -> Vec<(usize, TextSegment<'_>)> {
+    let is_newline = |ch: char| ch == '\n' && white_space.preserves_newlines();
+    let is_space = |ch: char| matches!(ch, ' ' | '\t' | '\n' | '\r') && !is_newline(ch);
+
+    let mut segments = Vec::new();
+    let mut offset = 0;
+    while let Some(ch) = text[offset..].chars().next() {
+        let rest = &text[offset..];
+        if is_newline(ch) {
+            segments.push((offset, TextSegment::Newline));
+            offset += 1;
+            continue;
+        }
+
+        if is_space(ch) {
+            let end = rest.find(|ch| !is_space(ch)).unwrap_or(rest.len());
+            let segment = if white_space.collapses_spaces() {
+                TextSegment::CollapsibleSpace
+            } else {
+                TextSegment::PreservedSpace(&rest[..end])
+            };
+            segments.push((offset, segment));
+            offset += end;
+            continue;
+        }
+
+        let end = rest
+            .find(|ch| is_space(ch) || is_newline(ch))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        let mut start = 0;
+        for (idx, opportunity) in unicode_linebreak::linebreaks(word) {
+            // The end of the text is always a break
+            if idx == word.len() {
+                break;
+            }
+            segments.push((offset + start, TextSegment::Word(&word[start..idx])));
+            let segment = match opportunity {
+                BreakOpportunity::Mandatory => TextSegment::Newline,
+                BreakOpportunity::Allowed => TextSegment::WordBreak,
+            };
+            segments.push((offset + idx, segment));
+            start = idx;
+        }
+        segments.push((offset + start, TextSegment::Word(&word[start..])));
+        offset += end;
+    }
+    segments
+}
+
+/// The bidi embedding level of each byte of `text`, which says which runs of it are drawn right-to-left. Each
+/// paragraph (separated by newlines) is resolved separately.
+pub(crate) fn bidi_levels(text: &str, rtl: bool) -> Vec<Level> {
+    let base = if rtl { Level::rtl() } else { Level::ltr() };
+    BidiInfo::new(text, Some(base)).levels
+}
+
+/// Vertical metrics of a line of text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineMetrics {
+    pub line_height: f32,
+    /// Offset of the baseline from the top of the line. Any extra space from line-height is split evenly above and
+    /// below the glyphs.
+    pub baseline: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionedGlyph {
+    pub id: u16,
+    pub font: FontId,
+    /// Offset of the glyph's origin from the start of the line
+    pub x: f32,
+    /// Offset of the glyph's origin below the baseline (e.g. for marks positioned by the font)
+    pub y: f32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TextLine {
+    pub glyphs: Vec<PositionedGlyph>,
+    /// Offset of the start of the line from the left of the text
+    pub x: f32,
+    pub width: f32,
+    /// Offset of the line's baseline from the top of the text
+    pub baseline: f32,
+}
+
+/// A run of text broken into lines
+#[derive(Clone, Debug, Default)]
+pub struct TextLayout {
+    pub lines: Vec<TextLine>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Shape a run of text that won't be broken across lines, returning its glyphs along with its width
+pub fn layout_word(
+    fonts: &FontContext,
+    word: &str,
+    style: &TextStyle,
+) -> (Vec<PositionedGlyph>, f32) {
+    fonts.shape(word, style)
+}
+
+/// Shape whitespace that isn't collapsed, with each tab as wide as [`TAB_SIZE`] spaces
+pub(crate) fn layout_preserved_space(
+    fonts: &FontContext,
+    space: &str,
+    style: &TextStyle,
+) -> (Vec<PositionedGlyph>, f32) {
+    let spaces: String = space
+        .chars()
+        .map(|ch| {
+            if ch == '\t' {
+                " ".repeat(TAB_SIZE)
+            } else {
+                " ".into()
+            }
+        })
+        .collect();
+    layout_word(fonts, &spaces, style)
+}
+
+/// Break text into lines no wider than `max_width` (if there is one) and position its glyphs.
+///
+/// Whitespace is collapsed and preserved according to `white-space`, and lines are only broken where UAX #14 allows, so
+/// a word wider than `max_width` overflows. Runs of right-to-left text are reordered within each line.
+pub fn layout_text(
+    fonts: &FontContext,
+    text: &str,
+    style: &TextStyle,
+    max_width: Option<f32>,
+) -> TextLayout {
+    let metrics = style.line_metrics(fonts);
+    let space_width = style.space_width(fonts);
+    let wraps = style.white_space.wraps();
+    let levels = bidi_levels(text, style.rtl);
+
+    let mut lines = Vec::new();
+    let mut line = LineBuilder::default();
+    let mut pending_space = None;
+    let mut can_break = false;
+    let finish_line = |lines: &mut Vec<TextLine>, line: &mut LineBuilder| {
+        let baseline = lines.len() as f32 * metrics.line_height + metrics.baseline;
+        lines.push(line.finish(baseline));
+    };
+
+    for (offset, segment) in segment_text(text, style.white_space) {
+        let (glyphs, width) = match segment {
+            TextSegment::Word(word) => layout_word(fonts, word, style),
+            TextSegment::PreservedSpace(space) => layout_preserved_space(fonts, space, style),
+            TextSegment::CollapsibleSpace => {
+                pending_space = Some(levels[offset]);
+                can_break = wraps;
+                continue;
+            }
+            TextSegment::WordBreak => {
+                can_break = wraps;
+                continue;
+            }
+            // Lines left empty by consecutive newlines still take up space
+            TextSegment::Newline => {
+                finish_line(&mut lines, &mut line);
+                pending_space = None;
+                can_break = false;
+                continue;
+            }
+        };
+
+        // Indentation stays on the line with the text that follows it
+        let line_started = !line.pieces.is_empty();
+        let breaks_after =
+            wraps && line_started && matches!(segment, TextSegment::PreservedSpace(_));
+        if line_started {
+            let space = if pending_space.is_some() {
+                space_width
+            } else {
+                0.0
+            };
+            let fits = max_width.map_or(true, |max_width| {
+                line.width + space + width <= max_width + LINE_FIT_EPSILON
+            });
+            if fits || !can_break {
+                if let Some(level) = pending_space {
+                    line.push(Vec::new(), space, level);
+                }
+            } else {
+                finish_line(&mut lines, &mut line);
+            }
+        }
+
+        line.push(glyphs, width, levels[offset]);
+        pending_space = None;
+        can_break = breaks_after;
+    }
+    if !line.pieces.is_empty() {
+        finish_line(&mut lines, &mut line);
+    }
+
+    TextLayout {
+        width: lines.iter().map(|line| line.width).fold(0.0, f32::max),
+        height: lines.len() as f32 * metrics.line_height,
+        lines,
+    }
+}
+
+/// A line of text being filled in logical order, which is put into visual order once it's full
+#[derive(Default)]
+struct LineBuilder {
+    /// Words and spaces, with glyphs positioned from the start of each, along with their width and bidi level
+    pieces: Vec<(Vec<PositionedGlyph>, f32, Level)>,
+    width: f32,
+}
+
+impl LineBuilder {
+    fn push(&mut self, glyphs: Vec<PositionedGlyph>, width: f32, level: Level) {
+        self.pieces.push((glyphs, width, level));
+        self.width += width;
+    }
+
+    fn finish(&mut self, baseline: f32) -> TextLine {
+        let levels: Vec<Level> = self.pieces.iter().map(|(_, _, level)| *level).collect();
+        let mut line = TextLine {
+            width: self.width,
+            baseline,
+            ..TextLine::default()
+        };
+        let mut x = 0.0;
+        for idx in BidiInfo::reorder_visual(&levels) {
+            let (glyphs, width, _) = &self.pieces[idx];
+            line.glyphs
+                .extend(glyphs.iter().map(|glyph| PositionedGlyph {
+                    x: glyph.x + x,
+                    ..*glyph
+                }));
+            x += width;
+        }
+
+        self.pieces.clear();
+        self.width = 0.0;
+        line
+    }
+}
+
+#[allow(dead_code)]
+pub enum WritingMode {
+    Horizontal,
+    Vertical,
+}
+
+pub struct TextContext<'a> {
+    pub text_content: &'a str,
+    pub writing_mode: WritingMode,
+}
+
+pub fn text_measure_function(
+    known_dimensions: taffy::geometry::Size<Option<f32>>,
+    available_space: taffy::geometry::Size<taffy::style::AvailableSpace>,
+    text_context: &TextContext,
+    text_style: &TextStyle,
+    fonts: &FontContext,
+) -> taffy::geometry::Size<f32> {
+    use taffy::geometry::AbsoluteAxis;
+    use taffy::prelude::*;
+
+    let inline_axis = match text_context.writing_mode {
+        WritingMode::Horizontal => AbsoluteAxis::Horizontal,
+        WritingMode::Vertical => AbsoluteAxis::Vertical,
+    };
+    let block_axis = inline_axis.other_axis();
+
+    let max_content = layout_text(fonts, text_context.text_content, text_style, None);
+    if max_content.lines.is_empty() {
+        return Size::ZERO;
+    }
+
+    let inline_size = known_dimensions.get_abs(inline_axis).unwrap_or_else(|| {
+        // Breaking at every opportunity leaves the longest word as the widest line
+        let min_content =
+            || layout_text(fonts, text_context.text_content, text_style, Some(0.0)).width;
+        match available_space.get_abs(inline_axis) {
+            AvailableSpace::MinContent => min_content(),
+            AvailableSpace::MaxContent => max_content.width,
+            AvailableSpace::Definite(inline_size) => {
+                inline_size.min(max_content.width).max(min_content())
+            }
+        }
+    });
+    let block_size = known_dimensions.get_abs(block_axis).unwrap_or_else(|| {
+        layout_text(
+            fonts,
+            text_context.text_content,
+            text_style,
+            Some(inline_size),
+        )
+        .height
+    });
+
+    match text_context.writing_mode {
+        WritingMode::Horizontal => Size {
+            width: inline_size,
+            height: block_size,
+        },
+        WritingMode::Vertical => Size {
+            width: block_size,
+            height: inline_size,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_between_words() {
+        let fonts = FontContext::default();
+        let style = TextStyle::default();
+        let single_line = layout_text(&fonts, "hello world", &style, None);
+        assert_eq!(single_line.lines.len(), 1);
+
+        let wrapped = layout_text(&fonts, "hello world", &style, Some(single_line.width - 1.0));
+        assert_eq!(wrapped.lines.len(), 2);
+        assert!(wrapped.width < single_line.width);
+        assert_eq!(wrapped.height, single_line.height * 2.0);
+
+        // Laying out again at the measured width must not wrap
+        let relaid = layout_text(&fonts, "hello world", &style, Some(single_line.width));
+        assert_eq!(relaid.lines.len(), 1);
+    }
+
+    #[test]
+    fn wraps_where_unicode_allows() {
+        let fonts = FontContext::default();
+        let style = TextStyle::default();
+
+        // CJK text can break between any two characters, and words can break after hyphens
+        let cjk = layout_text(&fonts, "日本語の文章", &style, Some(0.0));
+        assert_eq!(cjk.lines.len(), 6);
+        let hyphenated = layout_text(&fonts, "well-known", &style, Some(0.0));
+        assert_eq!(hyphenated.lines.len(), 2);
+
+        // But not where white-space doesn't allow wrapping
+        let style = TextStyle {
+            white_space: WhiteSpace::Nowrap,
+            ..style
+        };
+        assert_eq!(
+            layout_text(&fonts, "日本語", &style, Some(0.0)).lines.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn reorders_right_to_left_words() {
+        // Only the Hebrew words are right-to-left in a left-to-right paragraph
+        let text = "abc אב גד";
+        let levels = bidi_levels(text, false);
+        let word_levels: Vec<_> = ["abc", "אב", "גד"]
+            .map(|word| levels[text.find(word).unwrap()])
+            .into();
+        assert_eq!(word_levels, [Level::ltr(), Level::rtl(), Level::rtl()]);
+
+        // ...so the second of them is drawn first
+        let glyph = |id| PositionedGlyph {
+            id,
+            font: DEFAULT_FONT_ID,
+            x: 0.0,
+            y: 0.0,
+        };
+        let mut line = LineBuilder::default();
+        line.push(vec![glyph(1)], 30.0, word_levels[0]);
+        line.push(Vec::new(), 5.0, Level::ltr());
+        line.push(vec![glyph(2)], 20.0, word_levels[1]);
+        line.push(Vec::new(), 5.0, Level::rtl());
+        line.push(vec![glyph(3)], 10.0, word_levels[2]);
+        let line = line.finish(0.0);
+        let positions: Vec<_> = line
+            .glyphs
+            .iter()
+            .map(|glyph| (glyph.id, glyph.x))
+            .collect();
+        assert_eq!(positions, [(1, 0.0), (3, 35.0), (2, 50.0)]);
+        assert_eq!(line.width, 70.0);
+    }
+}
@@ -0,0 +1,207 @@
+//! Unpacking WOFF and WOFF2 web fonts into plain OpenType fonts
+
+use std::io::Read;
+
+/// Convert a downloaded font to an OpenType (TTF/OTF) font. Fonts that aren't WOFF or WOFF2 are assumed to be
+/// OpenType already and are passed through untouched.
+pub(crate) fn decode_font(data: Vec<u8>) -> Option<Vec<u8>> {
+    if data.starts_with(b"wOFF") {
+        decode_woff(&data)
+    } else if data.starts_with(b"wOF2") {
+        decode_woff2(&data)
+    } else {
+        Some(data)
+    }
+}
+
+const WOFF_HEADER_LEN: usize = 44;
+const WOFF_TABLE_ENTRY_LEN: usize = 20;
+const SFNT_HEADER_LEN: usize = 12;
+const SFNT_TABLE_RECORD_LEN: usize = 16;
+/// Web fonts come from untrusted pages, so don't unpack anything bigger than any real font
+const MAX_SFNT_LEN: usize = 64 * 1024 * 1024;
+
+/// WOFF 1.0 is an OpenType font with each table optionally zlib compressed
+/// https://www.w3.org/TR/WOFF/
+fn decode_woff(data: &[u8]) -> Option<Vec<u8>> {
+    let read_u16 = |offset: usize| {
+        Some(u16::from_be_bytes(
+            data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let read_u32 = |offset: usize| {
+        Some(u32::from_be_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+
+    let flavor = read_u32(4)?;
+    let num_tables = read_u16(12)?;
+    // Every table has to fit in the size the header claims for the unpacked font
+    let total_sfnt_size = read_u32(16)? as usize;
+    if total_sfnt_size > MAX_SFNT_LEN {
+        return None;
+    }
+
+    // The binary search parameters in the sfnt header are derived from the number of tables
+    let records_len = num_tables.checked_mul(16)?;
+    let entry_selector = (u16::BITS - 1 - num_tables.max(1).leading_zeros()) as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+    let range_shift = records_len.saturating_sub(search_range);
+
+    let mut font = Vec::new();
+    font.extend_from_slice(&flavor.to_be_bytes());
+    font.extend_from_slice(&num_tables.to_be_bytes());
+    font.extend_from_slice(&search_range.to_be_bytes());
+    font.extend_from_slice(&entry_selector.to_be_bytes());
+    font.extend_from_slice(&range_shift.to_be_bytes());
+
+    let mut table_offset = SFNT_HEADER_LEN + num_tables as usize * SFNT_TABLE_RECORD_LEN;
+    let mut tables = Vec::new();
+    for table_idx in 0..num_tables as usize {
+        let entry = WOFF_HEADER_LEN + table_idx * WOFF_TABLE_ENTRY_LEN;
+        let tag = read_u32(entry)?;
+        let offset = read_u32(entry + 4)? as usize;
+        let comp_length = read_u32(entry + 8)? as usize;
+        let orig_length = read_u32(entry + 12)? as usize;
+        let checksum = read_u32(entry + 16)?;
+
+        if comp_length > orig_length || table_offset + orig_length > total_sfnt_size {
+            return None;
+        }
+
+        let compressed = data.get(offset..offset.checked_add(comp_length)?)?;
+        let table = if comp_length < orig_length {
+            // Stop reading one byte past the claimed length, so that a table which unpacks to more than it says is
+            // rejected without unpacking all of it
+            let mut table = Vec::new();
+            flate2::read::ZlibDecoder::new(compressed)
+                .take(orig_length as u64 + 1)
+                .read_to_end(&mut table)
+                .ok()?;
+            table
+        } else {
+            compressed.to_vec()
+        };
+        if table.len() != orig_length {
+            return None;
+        }
+
+        font.extend_from_slice(&tag.to_be_bytes());
+        font.extend_from_slice(&checksum.to_be_bytes());
+        font.extend_from_slice(&(table_offset as u32).to_be_bytes());
+        font.extend_from_slice(&(orig_length as u32).to_be_bytes());
+
+        // Tables start on four byte boundaries
+        table_offset += (orig_length + 3) & !3;
+        tables.push(table);
+    }
+
+    for mut table in tables {
+        table.resize((table.len() + 3) & !3, 0);
+        font.extend_from_slice(&table);
+    }
+
+    Some(font)
+}
+
+/// WOFF 2.0 is an OpenType font with its tables transformed and then Brotli compressed
+/// https://www.w3.org/TR/WOFF2/
+fn decode_woff2(data: &[u8]) -> Option<Vec<u8>> {
+    // The header gives the size of the unpacked font in the same place as WOFF 1.0
+    let total_sfnt_size = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?) as usize;
+    if total_sfnt_size > MAX_SFNT_LEN {
+        return None;
+    }
+
+    let font = woff2::decode::convert_woff2_to_ttf(&mut &data[..]).ok()?;
+    (font.len() <= MAX_SFNT_LEN).then_some(font)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Pack tables into a WOFF file, compressing those that are marked to be
+    fn build_woff(tables: &[(&[u8; 4], &[u8], bool)], total_sfnt_size: u32) -> Vec<u8> {
+        let mut directory = Vec::new();
+        let mut table_data = Vec::new();
+        let data_start = WOFF_HEADER_LEN + tables.len() * WOFF_TABLE_ENTRY_LEN;
+        for (tag, table, compress) in tables {
+            let stored = if *compress {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(table).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                table.to_vec()
+            };
+            directory.extend_from_slice(*tag);
+            directory.extend_from_slice(&((data_start + table_data.len()) as u32).to_be_bytes());
+            directory.extend_from_slice(&(stored.len() as u32).to_be_bytes());
+            directory.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            directory.extend_from_slice(&0u32.to_be_bytes());
+            table_data.extend_from_slice(&stored);
+        }
+
+        let mut woff = Vec::new();
+        woff.extend_from_slice(b"wOFF");
+        woff.extend_from_slice(&0x00010000u32.to_be_bytes());
+        woff.extend_from_slice(&((data_start + table_data.len()) as u32).to_be_bytes());
+        woff.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        woff.extend_from_slice(&0u16.to_be_bytes());
+        woff.extend_from_slice(&total_sfnt_size.to_be_bytes());
+        woff.resize(WOFF_HEADER_LEN, 0);
+        woff.extend_from_slice(&directory);
+        woff.extend_from_slice(&table_data);
+        woff
+    }
+
+    #[test]
+    fn decodes_woff() {
+        let head = [7u8; 54];
+        let name = b"abc";
+        let woff = build_woff(&[(b"head", &head, true), (b"name", name, false)], 1024);
+
+        let font = decode_font(woff).unwrap();
+        assert_eq!(&font[..4], &0x00010000u32.to_be_bytes());
+        assert_eq!(u16::from_be_bytes([font[4], font[5]]), 2);
+
+        // Tables follow the records, each padded to four bytes
+        let tables_start = SFNT_HEADER_LEN + 2 * SFNT_TABLE_RECORD_LEN;
+        assert_eq!(&font[tables_start..tables_start + 54], &head);
+        assert_eq!(&font[tables_start + 56..tables_start + 59], name);
+        assert_eq!(font.len(), tables_start + 56 + 4);
+    }
+
+    #[test]
+    fn rejects_truncated_woff() {
+        let woff = build_woff(&[(b"head", &[7u8; 54], true)], 1024);
+        assert!(decode_font(woff[..woff.len() - 4].to_vec()).is_none());
+        assert!(decode_font(woff[..20].to_vec()).is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_tables() {
+        // The table claims to unpack to far more than the whole font
+        let mut woff = build_woff(&[(b"head", &[7u8; 54], true)], 1024);
+        let orig_length = WOFF_HEADER_LEN + 12;
+        woff[orig_length..orig_length + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode_font(woff).is_none());
+
+        // The header claims a font bigger than we're willing to unpack
+        let woff = build_woff(&[(b"head", &[7u8; 54], true)], u32::MAX);
+        assert!(decode_font(woff).is_none());
+
+        // WOFF2 fonts are held to the same limit
+        let mut woff2 = woff[..WOFF_HEADER_LEN].to_vec();
+        woff2[..4].copy_from_slice(b"wOF2");
+        assert!(decode_font(woff2).is_none());
+
+        // The table unpacks to more than it claims
+        let mut woff = build_woff(&[(b"head", &[7u8; 54], true)], 1024);
+        woff[orig_length..orig_length + 4].copy_from_slice(&40u32.to_be_bytes());
+        assert!(decode_font(woff).is_none());
+    }
+}
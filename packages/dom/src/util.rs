@@ -1,67 +1,87 @@
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
+use crate::net::{NetProvider, ResourceError, Response};
 use crate::node::{Node, NodeData};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
 use image::DynamicImage;
-
-const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:60.0) Gecko/20100101 Firefox/81.0";
-const FILE_SIZE_LIMIT: u64 = 1_000_000_000; // 1GB
-
-pub(crate) fn fetch_blob(url: &str) -> Result<Vec<u8>, ureq::Error> {
-    if url.starts_with("data:") {
-        let data_url = data_url::DataUrl::process(url).unwrap();
-        let decoded = data_url.decode_to_vec().expect("Invalid data url");
-        return Ok(decoded.0);
+use url::Url;
+
+pub(crate) fn fetch_blob(provider: &dyn NetProvider, url: &Url) -> Result<Response, ResourceError> {
+    if url.scheme() == "data" {
+        let invalid = || ResourceError::InvalidDataUrl(url.clone());
+        let data_url = data_url::DataUrl::process(url.as_str()).map_err(|_| invalid())?;
+        let (bytes, _) = data_url.decode_to_vec().map_err(|_| invalid())?;
+        return Ok(Response {
+            bytes,
+            content_type: Some(data_url.mime_type().to_string()),
+        });
     }
 
-    let resp = ureq::get(url).set("User-Agent", USER_AGENT).call()?;
-
-    let len: usize = resp
-        .header("Content-Length")
-        .and_then(|c| c.parse().ok())
-        .unwrap_or(0);
-    let mut bytes: Vec<u8> = Vec::with_capacity(len);
-
-    resp.into_reader()
-        .take(FILE_SIZE_LIMIT)
-        .read_to_end(&mut bytes)?;
+    provider
+        .fetch(url)
+        .map_err(|err| ResourceError::Fetch(url.clone(), err))
+}
 
-    Ok(bytes)
+/// Fetch a stylesheet and decode it to text
+pub(crate) fn fetch_css(provider: &dyn NetProvider, url: &Url) -> Result<String, ResourceError> {
+    let response = fetch_blob(provider, url)?;
+    Ok(decode_css(
+        &response.bytes,
+        response.content_type.as_deref(),
+    ))
 }
 
-pub(crate) fn fetch_string(url: &str) -> Result<String, ureq::Error> {
-    fetch_blob(url).map(|vec| String::from_utf8(vec).expect("Invalid UTF8"))
+/// Decode a stylesheet using the encoding given by its byte order mark, the charset of its `Content-Type` or its
+/// `@charset` rule (in that order of priority), falling back to UTF-8. Invalid bytes are replaced rather than
+/// rejecting the whole stylesheet.
+/// https://www.w3.org/TR/css-syntax-3/#input-byte-stream
+pub(crate) fn decode_css(bytes: &[u8], content_type: Option<&str>) -> String {
+    let encoding = content_type
+        .and_then(charset_param)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .or_else(|| charset_rule(bytes))
+        .unwrap_or(UTF_8);
+
+    // A byte order mark overrides the encoding we picked
+    let (css, _, _) = encoding.decode(bytes);
+    css.into_owned()
 }
 
-// pub(crate) fn fetch_buffered_stream(
-//     url: &str,
-// ) -> Result<impl BufRead + Read + Send + Sync, ureq::Error> {
-//     let resp = ureq::get(url).set("User-Agent", USER_AGENT).call()?;
-//     Ok(BufReader::new(resp.into_reader().take(FILE_SIZE_LIMIT)))
-// }
-
-#[allow(unused)]
-pub(crate) enum ImageFetchErr {
-    FetchErr(ureq::Error),
-    ImageError(image::error::ImageError),
+/// The `charset` parameter of a mime type, e.g. `text/css; charset="utf-8"`
+fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
 }
-impl From<ureq::Error> for ImageFetchErr {
-    fn from(value: ureq::Error) -> Self {
-        Self::FetchErr(value)
+
+/// The encoding named by an `@charset "...";` rule, which only counts if it's the very first thing in the stylesheet
+fn charset_rule(bytes: &[u8]) -> Option<&'static Encoding> {
+    let rest = bytes.strip_prefix(b"@charset \"")?;
+    let end = rest.iter().take(1024).position(|&b| b == b'"')?;
+    if rest.get(end + 1) != Some(&b';') {
+        return None;
     }
-}
-impl From<image::error::ImageError> for ImageFetchErr {
-    fn from(value: image::error::ImageError) -> Self {
-        Self::ImageError(value)
+
+    // A stylesheet that could be read well enough to find the rule can't really be UTF-16
+    match Encoding::for_label(&rest[..end])? {
+        encoding if encoding == UTF_16BE || encoding == UTF_16LE => Some(UTF_8),
+        encoding => Some(encoding),
     }
 }
 
-pub(crate) fn fetch_image(url: &str) -> Result<DynamicImage, ImageFetchErr> {
-    let blob = crate::util::fetch_blob(url)?;
-    let image = image::io::Reader::new(Cursor::new(blob))
+pub(crate) fn fetch_image(
+    provider: &dyn NetProvider,
+    url: &Url,
+) -> Result<DynamicImage, ResourceError> {
+    let blob = fetch_blob(provider, url)?;
+    image::io::Reader::new(Cursor::new(blob.bytes))
         .with_guessed_format()
         .expect("IO errors impossible with Cursor")
-        .decode()?;
-    Ok(image)
+        .decode()
+        .map_err(|err| ResourceError::InvalidImage(url.clone(), err))
 }
 
 // Debug print an RcDom
@@ -120,3 +140,23 @@ pub fn walk_tree(indent: usize, node: &Node) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_stylesheet_charset() {
+        let latin1 = b"@charset \"iso-8859-1\"; p::before { content: \"\xe9\" }";
+        assert!(decode_css(latin1, None).contains('\u{e9}'));
+
+        // The Content-Type header beats the @charset rule, and a byte order mark beats both
+        let utf8 = "@charset \"iso-8859-1\"; p::before { content: \"\u{e9}\" }";
+        assert!(decode_css(utf8.as_bytes(), Some("text/css; charset=utf-8")).contains('\u{e9}'));
+        let bom = [&b"\xef\xbb\xbf"[..], utf8.as_bytes()].concat();
+        assert!(decode_css(&bom, Some("text/css; charset=iso-8859-1")).starts_with("@charset"));
+
+        // Invalid bytes are replaced rather than failing
+        assert!(decode_css(b"p { color: red } \xff", None).contains('\u{fffd}'));
+    }
+}